@@ -0,0 +1,103 @@
+//! Delivering one response to each of 1k connections, as a room post does:
+//! through a single broadcast channel that every connection filters, and
+//! through a queue per connection the way `Worker` does it.
+
+use std::collections::HashMap;
+
+use chrono::Utc;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use server::outbox::{self, OverflowPolicy, OUTBOX_CAPACITY};
+use server::protocol::response::{MessageResponse, PostedResponse, ResponseData, UserResponse};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+const CONNECTIONS: usize = 1000;
+
+#[derive(Clone)]
+struct Addressed {
+    client_id: Uuid,
+    response_data: ResponseData,
+}
+
+fn posted() -> ResponseData {
+    let user = UserResponse::new(Uuid::new_v4(), "daolavi");
+    let message = MessageResponse::new(Uuid::new_v4(), Uuid::new_v4(), user, "Hello", Utc::now());
+    ResponseData::UserPosted(PostedResponse::new(message))
+}
+
+fn broadcast_and_filter(c: &mut Criterion, client_ids: &[Uuid], response_data: &ResponseData) {
+    // Large enough that nobody lags; the old 16 slots would not be
+    let (sender, _) = broadcast::channel(client_ids.len());
+    let mut receivers: Vec<(Uuid, broadcast::Receiver<Addressed>)> = client_ids
+        .iter()
+        .map(|client_id| (*client_id, sender.subscribe()))
+        .collect();
+
+    c.bench_with_input(
+        BenchmarkId::new("broadcast", CONNECTIONS),
+        response_data,
+        |b, response_data| {
+            b.iter(|| {
+                for client_id in client_ids {
+                    let addressed = Addressed {
+                        client_id: *client_id,
+                        response_data: response_data.clone(),
+                    };
+                    let _ = sender.send(addressed);
+                }
+                let mut delivered = 0;
+                for (client_id, receiver) in receivers.iter_mut() {
+                    while let Ok(addressed) = receiver.try_recv() {
+                        if addressed.client_id == *client_id {
+                            black_box(addressed.response_data);
+                            delivered += 1;
+                        }
+                    }
+                }
+                assert_eq!(delivered, client_ids.len());
+            })
+        },
+    );
+}
+
+fn per_client_queues(c: &mut Criterion, client_ids: &[Uuid], response_data: &ResponseData) {
+    let mut outboxes = HashMap::new();
+    let mut inboxes = Vec::new();
+    for client_id in client_ids {
+        let (outbox, inbox) = outbox::channel(OUTBOX_CAPACITY, OverflowPolicy::default());
+        outboxes.insert(*client_id, outbox);
+        inboxes.push(inbox);
+    }
+
+    c.bench_with_input(
+        BenchmarkId::new("per_client", CONNECTIONS),
+        response_data,
+        |b, response_data| {
+            b.iter(|| {
+                for client_id in client_ids {
+                    if let Some(outbox) = outboxes.get(client_id) {
+                        let _ = outbox.send(response_data.clone());
+                    }
+                }
+                let mut delivered = 0;
+                for inbox in inboxes.iter_mut() {
+                    while let Some(response_data) = inbox.try_recv() {
+                        black_box(response_data);
+                        delivered += 1;
+                    }
+                }
+                assert_eq!(delivered, client_ids.len());
+            })
+        },
+    );
+}
+
+fn fanout(c: &mut Criterion) {
+    let client_ids: Vec<Uuid> = (0..CONNECTIONS).map(|_| Uuid::new_v4()).collect();
+    let response_data = posted();
+    broadcast_and_filter(c, &client_ids, &response_data);
+    per_client_queues(c, &client_ids, &response_data);
+}
+
+criterion_group!(benches, fanout);
+criterion_main!(benches);
@@ -0,0 +1,280 @@
+use std::{collections::HashSet, convert::Infallible, sync::atomic::Ordering, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use warp::{http::StatusCode, reply, Filter, Rejection, Reply};
+
+use crate::{auth::Unauthorized, protocol::response::ErrorType, worker::Worker};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectedUser {
+    pub client_id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Announcement {
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Stats {
+    pub connections: usize,
+    pub users: usize,
+    pub rooms: usize,
+    pub messages: usize,
+    pub dropped_oldest: u64,
+    pub coalesced: u64,
+    pub disconnected: u64,
+}
+
+/// The `/admin` API, answering only requests that carry `token` as a bearer
+/// token. Without a token nothing is served.
+pub fn routes(
+    worker: Arc<Worker>,
+    token: Option<String>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let admin = warp::path("admin")
+        .and(authorize(token))
+        .and(warp::any().map(move || worker.clone()));
+
+    let users = admin
+        .clone()
+        .and(warp::path!("users"))
+        .and(warp::get())
+        .and_then(list_users);
+    let kick = admin
+        .clone()
+        .and(warp::path!("users" / Uuid / "kick"))
+        .and(warp::post())
+        .and_then(kick_user);
+    let announce = admin
+        .clone()
+        .and(warp::path!("announcements"))
+        .and(warp::post())
+        .and(warp::body::json())
+        .and_then(announce);
+    let delete = admin
+        .clone()
+        .and(warp::path!("messages" / Uuid))
+        .and(warp::delete())
+        .and_then(delete_message);
+    let stats = admin
+        .and(warp::path!("stats"))
+        .and(warp::get())
+        .and_then(stats);
+
+    users.or(kick).or(announce).or(delete).or(stats)
+}
+
+fn authorize(token: Option<String>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and_then(move |authorization: Option<String>| {
+            let token = token.clone();
+            async move {
+                let token = match token {
+                    Some(token) => token,
+                    None => return Err(warp::reject::not_found()),
+                };
+                let given = authorization
+                    .as_deref()
+                    .and_then(|value| value.strip_prefix("Bearer "))
+                    .unwrap_or_default();
+                if same_token(given.trim(), &token) {
+                    Ok(())
+                } else {
+                    Err(warp::reject::custom(Unauthorized))
+                }
+            }
+        })
+        .untuple_one()
+}
+
+// Compares in constant time, so response times say nothing about the token
+fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+async fn list_users(worker: Arc<Worker>) -> Result<impl Reply, Infallible> {
+    let mut users: Vec<ConnectedUser> = worker
+        .users
+        .read()
+        .await
+        .iter()
+        .map(|(client_id, user)| ConnectedUser {
+            client_id: *client_id,
+            user_id: user.id,
+            name: user.name.clone(),
+        })
+        .collect();
+    users.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(reply::json(&users))
+}
+
+async fn kick_user(worker: Arc<Worker>, user_id: Uuid) -> Result<impl Reply, Infallible> {
+    let status = match worker.kick(user_id).await {
+        0 => StatusCode::NOT_FOUND,
+        _ => StatusCode::NO_CONTENT,
+    };
+    Ok(status)
+}
+
+async fn announce(
+    worker: Arc<Worker>,
+    announcement: Announcement,
+) -> Result<impl Reply, Infallible> {
+    let text = announcement.text.trim();
+    if text.is_empty() {
+        return Ok(StatusCode::BAD_REQUEST);
+    }
+    worker.announce(text).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn delete_message(worker: Arc<Worker>, message_id: Uuid) -> Result<impl Reply, Infallible> {
+    let status = match worker.remove_message(message_id).await {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(ErrorType::MessageNotFound) => StatusCode::NOT_FOUND,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    Ok(status)
+}
+
+async fn stats(worker: Arc<Worker>) -> Result<impl Reply, Infallible> {
+    let (connections, users) = {
+        let users = worker.users.read().await;
+        let distinct: HashSet<Uuid> = users.values().map(|user| user.id).collect();
+        (worker.outboxes.read().unwrap().len(), distinct.len())
+    };
+    let rooms = worker.rooms.read().await.len();
+    let messages = {
+        let store = worker.feed.read().await;
+        store
+            .rooms()
+            .iter()
+            .filter_map(|room| store.feed(&room.id))
+            .map(|feed| feed.messages.len())
+            .sum()
+    };
+    let counters = &worker.overflow_counters;
+    Ok(reply::json(&Stats {
+        connections,
+        users,
+        rooms,
+        messages,
+        dropped_oldest: counters.dropped_oldest.load(Ordering::Relaxed),
+        coalesced: counters.coalesced.load(Ordering::Relaxed),
+        disconnected: counters.disconnected.load(Ordering::Relaxed),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use tokio::{runtime::Runtime, sync::mpsc};
+
+    use super::*;
+    use crate::{
+        client::Client,
+        outbox::{Outbound, KICKED_CLOSE_CODE},
+        protocol::{
+            request::{JoinRequestData, RequestData, RequestMessage},
+            response::ResponseData,
+        },
+    };
+
+    #[test]
+    fn operators_manage_users_with_the_admin_token() {
+        let worker = Arc::new(Worker::new(None));
+        let filter = routes(worker.clone(), Some(String::from("s3cret")));
+        let request = |method: &str, path: &str| {
+            warp::test::request()
+                .method(method)
+                .path(path)
+                .header("authorization", "Bearer s3cret")
+        };
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            let running = worker.clone();
+            tokio::spawn(async move { running.run(receiver).await });
+
+            let client = Client::with_identity(None);
+            let mut inbox = worker.on_connect(&client).await;
+            sender
+                .send(RequestMessage::new(
+                    client.id,
+                    RequestData::Join(JoinRequestData {
+                        name: String::from("daolavi"),
+                    }),
+                ))
+                .unwrap();
+            let user_id = match inbox.recv().await {
+                Some(ResponseData::Joined(joined)) => joined.user.id,
+                output => panic!("Expected Output::Joined got {:?}", output),
+            };
+
+            let response = warp::test::request()
+                .path("/admin/users")
+                .header("authorization", "Bearer guess")
+                .reply(&filter.clone().recover(crate::auth::recover))
+                .await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+            let response = request("GET", "/admin/users").reply(&filter).await;
+            let users: Vec<ConnectedUser> = serde_json::from_slice(response.body()).unwrap();
+            assert_eq!(users.len(), 1);
+            assert_eq!(
+                (users[0].user_id, users[0].name.as_str()),
+                (user_id, "daolavi")
+            );
+
+            let response = request("POST", "/admin/announcements")
+                .json(&Announcement {
+                    text: String::from("Restarting at noon"),
+                })
+                .reply(&filter)
+                .await;
+            assert_eq!(response.status(), StatusCode::NO_CONTENT);
+            match inbox.recv().await {
+                Some(ResponseData::Announcement(announcement)) => {
+                    assert_eq!(announcement.text, "Restarting at noon")
+                }
+                output => panic!("Expected Output::Announcement got {:?}", output),
+            }
+
+            let path = format!("/admin/messages/{}", Uuid::new_v4());
+            let response = request("DELETE", &path).reply(&filter).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+            let path = format!("/admin/users/{}/kick", user_id);
+            let response = request("POST", &path).reply(&filter).await;
+            assert_eq!(response.status(), StatusCode::NO_CONTENT);
+            let closing: Vec<Outbound> = inbox.into_stream().collect().await;
+            assert!(matches!(
+                closing.as_slice(),
+                [Outbound::Close(KICKED_CLOSE_CODE, _)]
+            ));
+
+            let response = request("GET", "/admin/stats").reply(&filter).await;
+            let stats: Stats = serde_json::from_slice(response.body()).unwrap();
+            assert_eq!((stats.connections, stats.users, stats.rooms), (0, 0, 1));
+        });
+
+        let response = Runtime::new().unwrap().block_on(
+            warp::test::request()
+                .path("/admin/stats")
+                .reply(&routes(worker, None)),
+        );
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,130 @@
+pub mod hmac;
+pub mod password;
+pub mod static_file;
+
+use std::sync::Arc;
+
+use log::debug;
+use warp::{http::StatusCode, reject::Reject, Filter, Rejection, Reply};
+
+pub const TOKEN_COOKIE: &str = "token";
+
+/// Who a connection was authenticated as.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    pub subject: String,
+    pub name: String,
+}
+
+impl Identity {
+    pub fn new(subject: &str, name: &str) -> Self {
+        Identity {
+            subject: String::from(subject),
+            name: String::from(name),
+        }
+    }
+}
+
+pub trait TokenVerifier: Send + Sync {
+    fn verify(&self, token: &str) -> Option<Identity>;
+}
+
+#[derive(Debug)]
+pub(crate) struct Unauthorized;
+
+impl Reject for Unauthorized {}
+
+/// The connection comes from a banned address.
+#[derive(Debug)]
+pub(crate) struct Banned;
+
+impl Reject for Banned {}
+
+/// Extracts the identity from a bearer token or the token cookie.
+/// Without a verifier every request passes through anonymously.
+pub fn authenticate(
+    verifier: Option<Arc<dyn TokenVerifier>>,
+) -> impl Filter<Extract = (Option<Identity>,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::cookie::optional::<String>(TOKEN_COOKIE))
+        .and_then(
+            move |authorization: Option<String>, cookie: Option<String>| {
+                let verifier = verifier.clone();
+                async move {
+                    let verifier = match verifier {
+                        Some(verifier) => verifier,
+                        None => return Ok(None),
+                    };
+                    let token = authorization
+                        .as_deref()
+                        .and_then(|value| value.strip_prefix("Bearer "))
+                        .map(String::from)
+                        .or(cookie);
+                    match token.and_then(|token| verifier.verify(token.trim())) {
+                        Some(identity) => Ok(Some(identity)),
+                        None => {
+                            debug!("Rejected connection without a valid token");
+                            Err(warp::reject::custom(Unauthorized))
+                        }
+                    }
+                }
+            },
+        )
+}
+
+pub async fn recover(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    if rejection.find::<Unauthorized>().is_some() {
+        Ok(warp::reply::with_status(
+            "Unauthorized",
+            StatusCode::UNAUTHORIZED,
+        ))
+    } else if rejection.find::<Banned>().is_some() {
+        Ok(warp::reply::with_status("Banned", StatusCode::FORBIDDEN))
+    } else {
+        Err(rejection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::runtime::Runtime;
+
+    use super::{static_file::StaticTokenVerifier, *};
+
+    #[test]
+    fn accepts_bearer_header_or_cookie() {
+        let verifier: Arc<dyn TokenVerifier> =
+            Arc::new(StaticTokenVerifier::parse("abc123 daolavi"));
+        let filter = authenticate(Some(verifier));
+        let expected = Some(Identity::new("daolavi", "daolavi"));
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let identity = warp::test::request()
+                .header("authorization", "Bearer abc123")
+                .filter(&filter)
+                .await
+                .unwrap();
+            assert_eq!(identity, expected);
+
+            let identity = warp::test::request()
+                .header("cookie", "token=abc123")
+                .filter(&filter)
+                .await
+                .unwrap();
+            assert_eq!(identity, expected);
+
+            let response = warp::test::request()
+                .header("authorization", "Bearer wrong")
+                .reply(&filter.clone().map(|_| "ok").recover(recover))
+                .await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+            let identity = warp::test::request()
+                .filter(&authenticate(None))
+                .await
+                .unwrap();
+            assert_eq!(identity, None);
+        });
+    }
+}
@@ -0,0 +1,54 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use super::{Identity, TokenVerifier};
+use crate::error::Result;
+
+/// Fixed tokens read from a file for local testing.
+///
+/// Each non-empty line holds a token and the user name it stands for,
+/// separated by whitespace. Lines starting with `#` are ignored.
+pub struct StaticTokenVerifier {
+    tokens: HashMap<String, Identity>,
+}
+
+impl StaticTokenVerifier {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    pub fn parse(contents: &str) -> Self {
+        let tokens = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let (token, name) = line.split_once(char::is_whitespace)?;
+                let name = name.trim();
+                Some((String::from(token), Identity::new(name, name)))
+            })
+            .collect();
+        StaticTokenVerifier { tokens }
+    }
+}
+
+impl TokenVerifier for StaticTokenVerifier {
+    fn verify(&self, token: &str) -> Option<Identity> {
+        self.tokens.get(token).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_token_lines() {
+        let verifier = StaticTokenVerifier::parse("# local users\nabc123 Dao Lam\n\nbroken\n");
+        assert_eq!(
+            verifier.verify("abc123"),
+            Some(Identity::new("Dao Lam", "Dao Lam"))
+        );
+        assert_eq!(verifier.verify("broken"), None);
+        assert_eq!(verifier.verify("nope"), None);
+    }
+}
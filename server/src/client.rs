@@ -1,57 +1,280 @@
-use std::{error, result};
+use std::{
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
 
-use futures::{Stream, StreamExt, TryStream, TryStreamExt, future, stream::SplitStream};
+use futures::{Stream, StreamExt, future};
 use uuid::Uuid;
-use warp::ws::WebSocket;
+use warp::ws::Message;
 
-use crate::{error::{Error, Result}, protocol::{request::RequestMessage, response::ResponseMessage}};
+use crate::{
+    auth::Identity,
+    error::{Error, Result},
+    outbox::Outbound,
+    protocol::{
+        request::{RequestData, RequestEnvelope, RequestMessage},
+        response::{ErrorType, WelcomeResponse},
+        encoding::Encoding,
+        version::Protocol,
+    },
+};
+
+/// A frame read from the socket: a request, or why it is not one.
+#[derive(Debug)]
+pub enum Inbound {
+    Request(RequestMessage),
+    /// The client said `Hello` and the protocol is settled.
+    Welcome(WelcomeResponse),
+    Invalid(ErrorType),
+}
 
 pub struct Client {
     pub id: Uuid,
+    pub identity: Option<Identity>,
+    /// Where the connection came from, when the transport knows.
+    pub address: Option<IpAddr>,
+    /// Chosen from the subprotocols offered on upgrade.
+    pub encoding: Encoding,
+    /// Settled by a `Hello` first message, or by any other one as legacy.
+    protocol: Arc<Mutex<Option<Protocol>>>,
 }
 
 impl Client {
     pub fn new() -> Self {
-        Client { id: Uuid::new_v4() }
+        Client {
+            id: Uuid::new_v4(),
+            identity: None,
+            address: None,
+            encoding: Encoding::default(),
+            protocol: Default::default(),
+        }
+    }
+
+    pub fn with_identity(identity: Option<Identity>) -> Self {
+        Client {
+            identity,
+            ..Client::new()
+        }
+    }
+
+    pub fn with_address(self, address: Option<IpAddr>) -> Self {
+        Client { address, ..self }
+    }
+
+    pub fn with_encoding(self, encoding: Encoding) -> Self {
+        Client { encoding, ..self }
     }
 
-    pub fn read(
-        &self,
-        stream: SplitStream<WebSocket>,
-    ) -> impl Stream<Item = Result<RequestMessage>> {
+    /// The protocol responses are written in, legacy until settled.
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap_or_else(Protocol::legacy)
+    }
+
+    pub fn read<S>(&self, stream: S) -> impl Stream<Item = Result<Inbound>>
+    where
+        S: Stream<Item = std::result::Result<Message, warp::Error>>,
+    {
         let client_id = self.id;
+        let encoding = self.encoding;
+        let protocol = self.protocol.clone();
         stream
-            // Take only text messages
+            // Read until the client closes the socket or it fails
             .take_while(|message| {
                 future::ready(match message {
-                    Ok(message) => message.is_text(),
+                    Ok(message) => !message.is_close(),
                     Err(_) => false,
                 })
             })
-            // Deserialize JSON messages into proto::Input
+            // Pings are answered by warp itself
+            .filter(|message| {
+                future::ready(match message {
+                    Ok(message) => !message.is_ping() && !message.is_pong(),
+                    Err(_) => true,
+                })
+            })
+            // Deserialize messages into proto::Input, keeping what went wrong
             .map(move |message| match message {
                 Err(err) => Err(Error::System(err.to_string())),
-                Ok(message) => {
-                    let input = serde_json::from_str(message.to_str().unwrap())?;
-                    Ok(RequestMessage::new(client_id, input))
-                }
+                Ok(message) => Ok(match encoding.decode::<RequestEnvelope>(&message) {
+                    Ok(input) => {
+                        let mut protocol = protocol.lock().unwrap();
+                        match (input.request_data, protocol.is_none()) {
+                            // A late `Hello` goes on to be refused with the other requests
+                            (RequestData::Hello(hello), true) => {
+                                match Protocol::negotiate(&hello) {
+                                    Ok(negotiated) => {
+                                        let welcome = negotiated.welcome();
+                                        *protocol = Some(negotiated);
+                                        Inbound::Welcome(welcome)
+                                    }
+                                    Err(error_type) => Inbound::Invalid(error_type),
+                                }
+                            }
+                            (request_data, _) => {
+                                protocol.get_or_insert_with(Protocol::legacy);
+                                Inbound::Request(
+                                    RequestMessage::new(client_id, request_data)
+                                        .with_request_id(input.request_id),
+                                )
+                            }
+                        }
+                    }
+                    Err(error_type) => Inbound::Invalid(error_type),
+                }),
             })
     }
 
-    pub fn write<S, E>(&self, stream: S) -> impl Stream<Item = Result<warp::ws::Message>>
+    pub fn write<S>(&self, stream: S) -> impl Stream<Item = Result<Message>>
     where
-        S: TryStream<Ok = ResponseMessage, Error = E> + Stream<Item = result::Result<ResponseMessage, E>>,
-        E: error::Error,
+        S: Stream<Item = Outbound>,
     {
-      let client_id = self.id;
+      let encoding = self.encoding;
+      let protocol = self.protocol.clone();
       stream
-          // Skip irrelevant parcels
-          .try_filter(move |output_parcel| future::ready(output_parcel.client_id == client_id))
-          // Serialize to JSON
-          .map_ok(|output_parcel| {
-              let data = serde_json::to_string(&output_parcel.response_data).unwrap();
-              warp::ws::Message::text(data)
+          // Serialize as the client's encoding and version of the protocol have it
+          .map(move |outbound| match outbound {
+              Outbound::Response(response_message) => match &*protocol.lock().unwrap() {
+                  Some(protocol) => protocol.encode(response_message, encoding),
+                  None => Protocol::legacy().encode(response_message, encoding),
+              },
+              Outbound::Close(code, reason) => Ok(Message::close_with(code, reason)),
           })
-          .map_err(|err| Error::System(err.to_string()))
     }
 }
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+  use futures::stream;
+  use tokio::runtime::Runtime;
+
+  use super::*;
+  use crate::protocol::response::{ResponseData, ResponseMessage};
+
+  #[test]
+  fn bad_frames_are_answered_without_ending_the_stream() {
+    let client = Client::new();
+    let frames = vec![
+      Message::text("{\"type\": \"listRooms\"}"),
+      Message::text("{\"type\": \"listRooms\",\n \"payload\": ]}"),
+      Message::binary(vec![1, 2, 3]),
+      Message::ping(vec![]),
+      Message::text("{\"type\": \"shout\"}"),
+      Message::close(),
+      Message::text("{\"type\": \"listRooms\"}"),
+    ];
+    let inbound: Vec<Inbound> = Runtime::new().unwrap().block_on(
+      client
+        .read(stream::iter(frames.into_iter().map(Ok)))
+        .map(|inbound| inbound.unwrap())
+        .collect(),
+    );
+
+    assert_eq!(inbound.len(), 4);
+    assert!(matches!(
+      &inbound[0],
+      Inbound::Request(request) if request.request_data == RequestData::ListRooms
+    ));
+    match &inbound[1] {
+      Inbound::Invalid(ErrorType::InvalidRequest { line, column, .. }) => {
+        assert_eq!((*line, *column), (2, 13))
+      }
+      inbound => panic!("Expected Inbound::Invalid got {:?}", inbound),
+    }
+    assert!(matches!(
+      &inbound[2],
+      Inbound::Invalid(ErrorType::InvalidRequest { line: 0, .. })
+    ));
+    match &inbound[3] {
+      Inbound::Invalid(ErrorType::InvalidRequest { message, .. }) => {
+        assert!(message.contains("unknown variant `shout`"))
+      }
+      inbound => panic!("Expected Inbound::Invalid got {:?}", inbound),
+    }
+  }
+  #[test]
+  fn hello_settles_the_protocol_of_later_responses() {
+    let client = Client::new();
+    let frames = vec![
+      Message::text("{\"type\": \"hello\", \"payload\": {\"protocolVersion\": 0}}"),
+      Message::text(
+        "{\"type\": \"hello\", \"payload\": {\"protocolVersion\": 2, \"capabilities\": [\"requestIds\"]}}",
+      ),
+      Message::text("{\"type\": \"hello\", \"payload\": {\"protocolVersion\": 1}}"),
+    ];
+    let rt = Runtime::new().unwrap();
+    let inbound: Vec<Inbound> = rt.block_on(
+      client
+        .read(stream::iter(frames.into_iter().map(Ok)))
+        .map(|inbound| inbound.unwrap())
+        .collect(),
+    );
+
+    assert!(matches!(&inbound[0], Inbound::Invalid(ErrorType::UnsupportedVersion)));
+    match &inbound[1] {
+      Inbound::Welcome(welcome) => {
+        assert_eq!(welcome.protocol_version, 2);
+        assert_eq!(welcome.capabilities, vec!["requestIds"]);
+      }
+      inbound => panic!("Expected Inbound::Welcome got {:?}", inbound),
+    }
+    assert!(matches!(
+      &inbound[2],
+      Inbound::Request(request) if matches!(request.request_data, RequestData::Hello(_))
+    ));
+    assert_eq!(client.protocol().version, 2);
+
+    let error = ResponseMessage::new(
+      ResponseData::Error(ErrorType::invalid_request("hello must be the first request")),
+      Some(String::from("1")),
+    );
+    let written: Vec<Message> = rt.block_on(
+      client
+        .write(stream::iter(vec![Outbound::Response(error)]))
+        .map(|message| message.unwrap())
+        .collect(),
+    );
+    let written: serde_json::Value = serde_json::from_str(written[0].to_str().unwrap()).unwrap();
+    assert_eq!(written["requestId"], "1");
+    assert!(written["payload"]["InvalidRequest"].is_object());
+  }
+  #[test]
+  fn legacy_clients_get_their_request_ids_back() {
+    let client = Client::new();
+    let frames = vec![Message::text("{\"type\": \"listRooms\", \"requestId\": \"42\"}")];
+    let rt = Runtime::new().unwrap();
+    let inbound: Vec<Inbound> = rt.block_on(
+      client
+        .read(stream::iter(frames.into_iter().map(Ok)))
+        .map(|inbound| inbound.unwrap())
+        .collect(),
+    );
+
+    let request_id = match &inbound[0] {
+      Inbound::Request(request) => request.request_id.clone(),
+      inbound => panic!("Expected Inbound::Request got {:?}", inbound),
+    };
+    assert_eq!(request_id.as_deref(), Some("42"));
+    assert_eq!(client.protocol(), Protocol::legacy());
+
+    let error = ResponseMessage::new(ResponseData::Error(ErrorType::NotJoined), request_id);
+    let written: Vec<Message> = rt.block_on(
+      client
+        .write(stream::iter(vec![Outbound::Response(error)]))
+        .map(|message| message.unwrap())
+        .collect(),
+    );
+    let written: serde_json::Value = serde_json::from_str(written[0].to_str().unwrap()).unwrap();
+    assert_eq!(written["requestId"], "42");
+    assert_eq!(written["payload"], "NotJoined");
+  }
+}
@@ -0,0 +1,460 @@
+use std::{
+    collections::HashMap,
+    env,
+    fmt::Display,
+    fs,
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use regex::Regex;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    error::{Error, Result},
+    outbox::{OverflowPolicy, OUTBOX_CAPACITY},
+    protocol::request::RequestData,
+    rate_limit::RateLimit,
+    worker::{HISTORY_PAGE_SIZE, MAX_HISTORY_PAGE_SIZE, RESUME_GRACE_PERIOD, TYPING_TIMEOUT},
+};
+
+pub const DEFAULT_USER_NAME_PATTERN: &str = "[A-Za-z\\s]{4,24}";
+pub const DEFAULT_ROOM_NAME_PATTERN: &str = "^[A-Za-z0-9_\\-\\s]{2,32}$";
+const MIN_FRAME_SIZE: usize = 1 << 10;
+const MAX_FRAME_SIZE: usize = 1 << 24;
+
+/// Settings that can be given as `--kebab-case` flags and `UPPER_CASE`
+/// environment variables as well as in the TOML file.
+const KEYS: [&str; 25] = [
+    "bind_address",
+    "port",
+    "max_frame_size",
+    "alive_interval_secs",
+    "outbox_capacity",
+    "overflow_policy",
+    "resume_grace_period_secs",
+    "typing_timeout_secs",
+    "history_page_size",
+    "max_history_page_size",
+    "user_name_pattern",
+    "room_name_pattern",
+    "feed_path",
+    "auth_hmac_secret",
+    "auth_tokens_file",
+    "tls_cert_path",
+    "tls_key_path",
+    "admin_token",
+    "moderators",
+    "moderator_accounts",
+    "bans_path",
+    "rate_limits",
+    "address_rate_limits",
+    "rate_limit_strikes",
+    "max_invalid_frames",
+];
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind_address: IpAddr,
+    pub port: u16,
+    pub max_frame_size: usize,
+    /// Seconds between `Alive` ticks; 0 turns them off.
+    pub alive_interval_secs: u64,
+    pub outbox_capacity: usize,
+    pub overflow_policy: OverflowPolicy,
+    pub resume_grace_period_secs: u64,
+    /// Seconds after which a user who never sent `StopTyping` stops typing.
+    pub typing_timeout_secs: u64,
+    pub history_page_size: usize,
+    pub max_history_page_size: usize,
+    pub user_name_pattern: String,
+    pub room_name_pattern: String,
+    /// JSON-lines file to keep the feed in; memory only when unset.
+    pub feed_path: Option<PathBuf>,
+    pub auth_hmac_secret: Option<String>,
+    pub auth_tokens_file: Option<PathBuf>,
+    /// PEM certificate chain and private key; both set to serve `wss://`.
+    pub tls_cert_path: Option<PathBuf>,
+    pub tls_key_path: Option<PathBuf>,
+    /// Bearer token for the `/admin` API, which is not served when unset.
+    pub admin_token: Option<String>,
+    /// Names that moderate once a token verifier vouches for them. Anyone
+    /// can register a name nobody has taken yet, so accounts are not trusted
+    /// by name. Given as a comma-separated list outside the TOML file.
+    pub moderators: Vec<String>,
+    /// Ids of the accounts that moderate, given the same way.
+    pub moderator_accounts: Vec<Uuid>,
+    /// JSON-lines file to keep bans in; memory only when unset.
+    pub bans_path: Option<PathBuf>,
+    /// Limits per request `type`, applied to each client. Given as
+    /// `type=requests/seconds` pairs separated by commas outside the TOML
+    /// file, which override the defaults one by one.
+    pub rate_limits: HashMap<String, RateLimit>,
+    /// Limits per request `type` shared by all the clients of an address,
+    /// which may be a whole NAT or proxy. Given the same way.
+    pub address_rate_limits: HashMap<String, RateLimit>,
+    /// Requests rejected within a minute before the client is disconnected.
+    pub rate_limit_strikes: u32,
+    /// Frames in a row that are not valid requests before the connection
+    /// is closed.
+    pub max_invalid_frames: u32,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 8080,
+            max_frame_size: 1 << 16,
+            alive_interval_secs: 5,
+            outbox_capacity: OUTBOX_CAPACITY,
+            overflow_policy: OverflowPolicy::default(),
+            resume_grace_period_secs: RESUME_GRACE_PERIOD.as_secs(),
+            typing_timeout_secs: TYPING_TIMEOUT.as_secs(),
+            history_page_size: HISTORY_PAGE_SIZE,
+            max_history_page_size: MAX_HISTORY_PAGE_SIZE,
+            user_name_pattern: String::from(DEFAULT_USER_NAME_PATTERN),
+            room_name_pattern: String::from(DEFAULT_ROOM_NAME_PATTERN),
+            feed_path: None,
+            auth_hmac_secret: None,
+            auth_tokens_file: None,
+            tls_cert_path: None,
+            tls_key_path: None,
+            admin_token: None,
+            moderators: Vec::new(),
+            moderator_accounts: Vec::new(),
+            bans_path: None,
+            rate_limits: default_rate_limits(),
+            address_rate_limits: default_address_rate_limits(),
+            rate_limit_strikes: 10,
+            max_invalid_frames: 5,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Defaults, overridden by the TOML file named by `--config` or
+    /// `CONFIG_PATH`, then by environment variables, then by flags.
+    pub fn load(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        Self::load_with(args, |name| env::var(name).ok())
+    }
+
+    pub fn load_with(
+        args: impl IntoIterator<Item = String>,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self> {
+        let flags = parse_flags(args)?;
+        let path = flags
+            .iter()
+            .find(|(key, _)| key == "config")
+            .map(|(_, path)| path.clone())
+            .or_else(|| var("CONFIG_PATH"));
+        let mut config = match path {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        for key in KEYS.iter() {
+            if let Some(value) = var(&key.to_uppercase()) {
+                config.set(key, &value)?;
+            }
+        }
+        for (key, value) in flags.iter().filter(|(key, _)| key != "config") {
+            config.set(key, value)?;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|err| Error::Config(format!("{}: {}", path.display(), err)))?;
+        toml::from_str(&contents)
+            .map_err(|err| Error::Config(format!("{}: {}", path.display(), err)))
+    }
+
+    pub fn alive_interval(&self) -> Option<Duration> {
+        match self.alive_interval_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    pub fn resume_grace_period(&self) -> Duration {
+        Duration::from_secs(self.resume_grace_period_secs)
+    }
+
+    pub fn typing_timeout(&self) -> Duration {
+        Duration::from_secs(self.typing_timeout_secs)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !(MIN_FRAME_SIZE..=MAX_FRAME_SIZE).contains(&self.max_frame_size) {
+            return Err(Error::Config(format!(
+                "max_frame_size must be between {} and {} bytes",
+                MIN_FRAME_SIZE, MAX_FRAME_SIZE
+            )));
+        }
+        if self.outbox_capacity == 0 {
+            return Err(Error::Config(String::from(
+                "outbox_capacity must be at least 1",
+            )));
+        }
+        if self.typing_timeout_secs == 0 {
+            return Err(Error::Config(String::from(
+                "typing_timeout_secs must be at least 1",
+            )));
+        }
+        if self.history_page_size == 0 || self.history_page_size > self.max_history_page_size {
+            return Err(Error::Config(String::from(
+                "history_page_size must be between 1 and max_history_page_size",
+            )));
+        }
+        self.user_name_regex()?;
+        self.room_name_regex()?;
+        if self.auth_hmac_secret.is_some() && self.auth_tokens_file.is_some() {
+            return Err(Error::Config(String::from(
+                "auth_hmac_secret and auth_tokens_file cannot both be set",
+            )));
+        }
+        if self.tls_cert_path.is_some() != self.tls_key_path.is_some() {
+            return Err(Error::Config(String::from(
+                "tls_cert_path and tls_key_path must be set together",
+            )));
+        }
+        if self.max_invalid_frames == 0 {
+            return Err(Error::Config(String::from(
+                "max_invalid_frames must be at least 1",
+            )));
+        }
+        if self.admin_token.as_deref().is_some_and(str::is_empty) {
+            return Err(Error::Config(String::from("admin_token cannot be empty")));
+        }
+        for (key, rate_limits) in [
+            ("rate_limits", &self.rate_limits),
+            ("address_rate_limits", &self.address_rate_limits),
+        ] {
+            let mut unknown: Vec<&str> = rate_limits
+                .keys()
+                .map(String::as_str)
+                .filter(|kind| !RequestData::KINDS.contains(kind))
+                .collect();
+            if !unknown.is_empty() {
+                unknown.sort_unstable();
+                return Err(Error::Config(format!(
+                    "{} has unknown request types {}; expected any of {}",
+                    key,
+                    unknown.join(", "),
+                    RequestData::KINDS.join(", ")
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn user_name_regex(&self) -> Result<Regex> {
+        compile("user_name_pattern", &self.user_name_pattern)
+    }
+
+    pub fn room_name_regex(&self) -> Result<Regex> {
+        compile("room_name_pattern", &self.room_name_pattern)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "bind_address" => self.bind_address = parse(key, value)?,
+            "port" => self.port = parse(key, value)?,
+            "max_frame_size" => self.max_frame_size = parse(key, value)?,
+            "alive_interval_secs" => self.alive_interval_secs = parse(key, value)?,
+            "outbox_capacity" => self.outbox_capacity = parse(key, value)?,
+            "overflow_policy" => self.overflow_policy = parse(key, value)?,
+            "resume_grace_period_secs" => self.resume_grace_period_secs = parse(key, value)?,
+            "typing_timeout_secs" => self.typing_timeout_secs = parse(key, value)?,
+            "history_page_size" => self.history_page_size = parse(key, value)?,
+            "max_history_page_size" => self.max_history_page_size = parse(key, value)?,
+            "user_name_pattern" => self.user_name_pattern = String::from(value),
+            "room_name_pattern" => self.room_name_pattern = String::from(value),
+            "feed_path" => self.feed_path = Some(PathBuf::from(value)),
+            "auth_hmac_secret" => self.auth_hmac_secret = Some(String::from(value)),
+            "auth_tokens_file" => self.auth_tokens_file = Some(PathBuf::from(value)),
+            "tls_cert_path" => self.tls_cert_path = Some(PathBuf::from(value)),
+            "tls_key_path" => self.tls_key_path = Some(PathBuf::from(value)),
+            "admin_token" => self.admin_token = Some(String::from(value)),
+            "moderators" => self.moderators = parse_list(key, value)?,
+            "moderator_accounts" => self.moderator_accounts = parse_list(key, value)?,
+            "bans_path" => self.bans_path = Some(PathBuf::from(value)),
+            "rate_limits" => set_rate_limits(&mut self.rate_limits, key, value)?,
+            "address_rate_limits" => set_rate_limits(&mut self.address_rate_limits, key, value)?,
+            "rate_limit_strikes" => self.rate_limit_strikes = parse(key, value)?,
+            "max_invalid_frames" => self.max_invalid_frames = parse(key, value)?,
+            _ => return Err(Error::Config(format!("unknown setting {}", key))),
+        }
+        Ok(())
+    }
+}
+
+fn default_rate_limits() -> HashMap<String, RateLimit> {
+    let limit = |requests, secs| RateLimit::new(requests, Duration::from_secs(secs));
+    vec![
+        ("join", limit(3, 60)),
+        ("register", limit(3, 60)),
+        ("login", limit(5, 60)),
+        ("resume", limit(5, 60)),
+        ("postMessage", limit(5, 5)),
+        ("directMessage", limit(5, 5)),
+        ("createRoom", limit(3, 60)),
+    ]
+    .into_iter()
+    .map(|(kind, limit)| (String::from(kind), limit))
+    .collect()
+}
+
+/// Ten times the limits of a client, so that a few dozen people sharing an
+/// address get by as long as they do not all flood.
+fn default_address_rate_limits() -> HashMap<String, RateLimit> {
+    default_rate_limits()
+        .into_iter()
+        .map(|(kind, limit)| (kind, RateLimit::new(limit.requests * 10, limit.per)))
+        .collect()
+}
+
+/// Overrides the limits in `rate_limits` named by `type=limit` pairs.
+fn set_rate_limits(
+    rate_limits: &mut HashMap<String, RateLimit>,
+    key: &str,
+    value: &str,
+) -> Result<()> {
+    for pair in value.split(',').filter(|pair| !pair.trim().is_empty()) {
+        let (kind, limit) = pair.split_once('=').ok_or_else(|| {
+            Error::Config(format!("invalid {} {:?}: expected type=limit", key, pair))
+        })?;
+        rate_limits.insert(String::from(kind.trim()), parse(key, limit)?);
+    }
+    Ok(())
+}
+
+/// `--key value` and `--key=value` pairs, keys in snake_case.
+fn parse_flags(args: impl IntoIterator<Item = String>) -> Result<Vec<(String, String)>> {
+    let mut args = args.into_iter();
+    let mut flags = Vec::new();
+    while let Some(arg) = args.next() {
+        let flag = match arg.strip_prefix("--") {
+            Some(flag) => flag,
+            None => return Err(Error::Config(format!("unexpected argument {}", arg))),
+        };
+        let (key, value) = match flag.split_once('=') {
+            Some((key, value)) => (key, String::from(value)),
+            None => match args.next() {
+                Some(value) => (flag, value),
+                None => return Err(Error::Config(format!("missing value for --{}", flag))),
+            },
+        };
+        flags.push((key.replace('-', "_"), value));
+    }
+    Ok(flags)
+}
+
+fn parse<T>(key: &str, value: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .parse()
+        .map_err(|err| Error::Config(format!("invalid {} {:?}: {}", key, value, err)))
+}
+
+/// Comma-separated values, ignoring blanks.
+fn parse_list<T>(key: &str, value: &str) -> Result<Vec<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| parse(key, item))
+        .collect()
+}
+
+fn compile(key: &str, pattern: &str) -> Result<Regex> {
+    Regex::new(pattern).map_err(|err| Error::Config(format!("invalid {}: {}", key, err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| String::from(*arg)).collect()
+    }
+
+    #[test]
+    fn flags_override_env_override_file() {
+        let path = env::temp_dir().join(format!("server-{}.toml", Uuid::new_v4()));
+        fs::write(
+            &path,
+            "port = 9000\nalive_interval_secs = 0\noverflow_policy = \"drop_oldest\"\n",
+        )
+        .unwrap();
+
+        let var = |name: &str| match name {
+            "CONFIG_PATH" => Some(path.display().to_string()),
+            "PORT" => Some(String::from("9001")),
+            "BIND_ADDRESS" => Some(String::from("0.0.0.0")),
+            "RATE_LIMITS" => Some(String::from("postMessage=1/2, search=10/60")),
+            _ => None,
+        };
+        let config = ServerConfig::load_with(args(&["--port", "9002"]), var).unwrap();
+        assert_eq!(config.port, 9002);
+        assert_eq!(config.bind_address, IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        assert_eq!(config.alive_interval(), None);
+        assert_eq!(config.overflow_policy, OverflowPolicy::DropOldest);
+        assert_eq!(config.history_page_size, HISTORY_PAGE_SIZE);
+        assert_eq!(
+            config.rate_limits["postMessage"],
+            RateLimit::new(1, Duration::from_secs(2))
+        );
+        assert_eq!(config.rate_limits["join"], default_rate_limits()["join"]);
+        assert!(config.rate_limits.contains_key("search"));
+        assert_eq!(
+            config.address_rate_limits["join"],
+            RateLimit::new(30, Duration::from_secs(60))
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        let load = |flags: &[&str]| ServerConfig::load_with(args(flags), |_| None);
+        assert!(load(&[]).is_ok());
+        assert!(load(&["--port", "http"]).is_err());
+        assert!(load(&["--colour=blue"]).is_err());
+        assert!(load(&["--outbox-capacity=0"]).is_err());
+        assert!(load(&["--typing-timeout-secs=0"]).is_err());
+        assert!(load(&["--history-page-size", "500"]).is_err());
+        assert!(load(&["--user-name-pattern", "[a-z"]).is_err());
+        assert!(load(&["--overflow-policy", "ignore"]).is_err());
+        assert!(load(&["--port"]).is_err());
+        assert!(load(&["--tls-cert-path", "cert.pem"]).is_err());
+        assert!(load(&["--admin-token="]).is_err());
+        assert!(load(&["--max-invalid-frames", "0"]).is_err());
+        assert!(load(&["--moderator-accounts", "alice"]).is_err());
+        assert!(load(&["--rate-limits", "postMessage"]).is_err());
+        assert!(load(&["--rate-limits", "postMessage=0/5"]).is_err());
+        assert!(load(&["--rate-limits", "postMesage=1/2"]).is_err());
+        assert!(load(&["--address-rate-limits", "postMessage=1/2,Join=1/2"]).is_err());
+        assert!(ServerConfig::from_file("/nonexistent/server.toml").is_err());
+        assert!(toml::from_str::<ServerConfig>("prot = 8080").is_err());
+    }
+}
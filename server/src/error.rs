@@ -5,6 +5,8 @@ pub enum Error {
     System(String),
     Io(io::Error),
     Message(serde_json::Error),
+    Config(String),
+    Encoding(String),
 }
 
 impl fmt::Display for Error {
@@ -13,6 +15,8 @@ impl fmt::Display for Error {
             Error::System(err) => write!(f, "system error: {}", err),
             Error::Io(ref err) => write!(f, "IO error: {}", err),
             Error::Message(ref err) => write!(f, "Invalid message: {}", err),
+            Error::Config(err) => write!(f, "invalid configuration: {}", err),
+            Error::Encoding(err) => write!(f, "encoding error: {}", err),
         }
     }
 }
@@ -1,9 +1,13 @@
-#[macro_use]
-extern crate lazy_static;
-
+pub mod admin;
+pub mod auth;
 pub mod client;
+pub mod config;
 pub mod error;
 pub mod worker;
 pub mod model;
+pub mod outbox;
 pub mod protocol;
-pub mod server;
\ No newline at end of file
+pub mod rate_limit;
+pub mod server;
+pub mod store;
+pub mod tls;
\ No newline at end of file
@@ -1,9 +1,41 @@
-use server::server::Server;
+use std::{env, process, sync::Arc};
+
+use server::{
+    auth::{hmac::HmacTokenVerifier, static_file::StaticTokenVerifier, TokenVerifier},
+    config::ServerConfig,
+    server::Server,
+    store::{file::JsonLinesFeedStore, memory::MemoryFeedStore, FeedStore},
+};
 
 #[tokio::main]
 async fn main() {
   env_logger::init();
 
-  let server = Server::new(8080);
+  let config = match ServerConfig::load(env::args().skip(1)) {
+    Ok(config) => config,
+    Err(err) => {
+      eprintln!("{}", err);
+      process::exit(2);
+    }
+  };
+
+  let store: Box<dyn FeedStore> = match &config.feed_path {
+    Some(path) => Box::new(JsonLinesFeedStore::open(path).expect("failed to open feed store")),
+    None => Box::new(MemoryFeedStore::default()),
+  };
+
+  let verifier: Option<Arc<dyn TokenVerifier>> = if let Some(secret) = &config.auth_hmac_secret {
+    Some(Arc::new(HmacTokenVerifier::new(secret.as_bytes())))
+  } else if let Some(path) = &config.auth_tokens_file {
+    Some(Arc::new(StaticTokenVerifier::load(path).expect("failed to load tokens file")))
+  } else {
+    None
+  };
+
+  let mut server = Server::with_config(config, store).expect("failed to load feed store");
+  if let Some(verifier) = verifier {
+    server = server.with_verifier(verifier);
+  }
+
   server.run().await;
-}
\ No newline at end of file
+}
@@ -0,0 +1,24 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// A registered user. Its id stays the same across connections.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Account {
+    pub id: Uuid,
+    pub name: String,
+    pub password_hash: String,
+    pub created_at_utc: DateTime<Utc>,
+}
+
+impl Account {
+    pub fn new(id: Uuid, name: &str, password_hash: &str, created_at_utc: DateTime<Utc>) -> Self {
+        Account {
+            id,
+            name: String::from(name),
+            password_hash: String::from(password_hash),
+            created_at_utc,
+        }
+    }
+}
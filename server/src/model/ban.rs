@@ -0,0 +1,54 @@
+use std::net::IpAddr;
+
+use serde::{Deserialize, Serialize};
+
+/// Who may not come back: everyone going by a name, or connecting from an
+/// address.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload", rename_all = "camelCase")]
+pub enum Ban {
+    Name(String),
+    Address(#[serde(with = "address_text")] IpAddr),
+}
+
+/// Writes addresses as text in every encoding. Binary ones would write
+/// octets otherwise, which cannot be read back from a flattened envelope.
+pub mod address_text {
+    use std::net::IpAddr;
+
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(address: &IpAddr, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(address)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<IpAddr, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+
+    pub mod optional {
+        use std::net::IpAddr;
+
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            address: &Option<IpAddr>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match address {
+                Some(address) => super::serialize(address, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<IpAddr>, D::Error> {
+            Option::<String>::deserialize(deserializer)?
+                .map(|address| address.parse().map_err(serde::de::Error::custom))
+                .transpose()
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+
+use super::user::User;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+#[derive(Debug, Clone)]
+pub struct DirectMessage {
+    pub id: Uuid,
+    pub from: User,
+    pub to: User,
+    pub text: String,
+    pub created_at_utc: DateTime<Utc>,
+}
+
+impl DirectMessage {
+    pub fn new(id: Uuid, from: User, to: User, text: &str, created_at_utc: DateTime<Utc>) -> Self {
+        DirectMessage {
+            id,
+            from,
+            to,
+            text: String::from(text),
+            created_at_utc,
+        }
+    }
+}
+
+/// Private histories, one per pair of users regardless of who wrote first.
+#[derive(Default)]
+pub struct Conversations {
+    conversations: HashMap<(Uuid, Uuid), Vec<DirectMessage>>,
+}
+
+impl Conversations {
+    pub fn add_message(&mut self, message: DirectMessage) {
+        self.conversations
+            .entry(Self::key(message.from.id, message.to.id))
+            .or_default()
+            .push(message);
+    }
+
+    pub fn history(
+        &self,
+        user_id: Uuid,
+        other_user_id: Uuid,
+    ) -> impl Iterator<Item = &DirectMessage> {
+        self.conversations
+            .get(&Self::key(user_id, other_user_id))
+            .into_iter()
+            .flatten()
+    }
+
+    fn key(user_id: Uuid, other_user_id: Uuid) -> (Uuid, Uuid) {
+        if user_id <= other_user_id {
+            (user_id, other_user_id)
+        } else {
+            (other_user_id, user_id)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_is_shared_by_both_users() {
+        let alice = User::new(Uuid::new_v4(), "alice");
+        let bob = User::new(Uuid::new_v4(), "bobby");
+        let mut conversations = Conversations::default();
+        let hi = DirectMessage::new(Uuid::new_v4(), alice.clone(), bob.clone(), "Hi", Utc::now());
+        let hey = DirectMessage::new(
+            Uuid::new_v4(),
+            bob.clone(),
+            alice.clone(),
+            "Hey",
+            Utc::now(),
+        );
+        conversations.add_message(hi);
+        conversations.add_message(hey);
+
+        let texts: Vec<&str> = conversations
+            .history(bob.id, alice.id)
+            .map(|message| message.text.as_str())
+            .collect();
+        assert_eq!(texts, vec!["Hi", "Hey"]);
+        assert_eq!(conversations.history(alice.id, Uuid::new_v4()).count(), 0);
+    }
+}
@@ -1,18 +1,242 @@
-use super::message::Message;
+use std::collections::{BTreeSet, HashMap};
+
+use super::{
+  message::Message,
+  search::{self, SearchQuery},
+};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
 
 #[derive(Default)]
 pub struct Feed {
-  pub messages: Vec<Message>
+  pub messages: Vec<Message>,
+  created_at_by_id: HashMap<Uuid, DateTime<Utc>>,
+  replies_by_parent: HashMap<Uuid, Vec<(DateTime<Utc>, Uuid)>>,
+  messages_by_word: HashMap<String, BTreeSet<(DateTime<Utc>, Uuid)>>,
 }
 
 impl Feed {
-  pub fn add_message(&mut self, message: Message) {
-    self.messages.push(message);
-    self.messages.sort_by_key(|message| message.created_at_utc)
+  pub fn add_message(&mut self, mut message: Message) {
+    let key = (message.created_at_utc, message.id);
+    if let Some(parent_id) = message.parent_id {
+      let replies = self.replies_by_parent.entry(parent_id).or_default();
+      replies.insert(replies.partition_point(|reply| *reply < key), key);
+      if let Some(parent) = self.get_mut(&parent_id) {
+        parent.reply_count += 1;
+      }
+    }
+    message.reply_count = self.replies_by_parent.get(&message.id).map_or(0, Vec::len);
+    self.index(key, &message.text);
+
+    let index = self.position(key);
+    self.created_at_by_id.insert(message.id, message.created_at_utc);
+    self.messages.insert(index, message);
+  }
+
+  pub fn edit_message(&mut self, id: &Uuid, text: &str, edited_at_utc: DateTime<Utc>) {
+    let (key, previous_text) = match self.get(id) {
+      Some(message) => ((message.created_at_utc, message.id), message.text.clone()),
+      None => return,
+    };
+    self.unindex(key, &previous_text);
+    self.index(key, text);
+    self.get_mut(id).unwrap().edit(text, edited_at_utc);
+  }
+
+  pub fn delete_message(&mut self, id: &Uuid) {
+    let (key, previous_text) = match self.get(id) {
+      Some(message) => ((message.created_at_utc, message.id), message.text.clone()),
+      None => return,
+    };
+    self.unindex(key, &previous_text);
+    self.get_mut(id).unwrap().delete();
   }
 
   pub fn iter(&self) -> impl Iterator<Item = &Message> {
     self.messages.iter()
   }
+
+  pub fn get(&self, id: &Uuid) -> Option<&Message> {
+    let created_at_utc = self.created_at_by_id.get(id)?;
+    self.messages.get(self.position((*created_at_utc, *id)))
+  }
+
+  pub fn get_mut(&mut self, id: &Uuid) -> Option<&mut Message> {
+    let created_at_utc = self.created_at_by_id.get(id)?;
+    let index = self.position((*created_at_utc, *id));
+    self.messages.get_mut(index)
+  }
+
+  /// The newest `limit` messages, oldest first.
+  pub fn latest(&self, limit: usize) -> &[Message] {
+    &self.messages[self.messages.len().saturating_sub(limit)..]
+  }
+
+  /// Messages ordered after the message created at `created_at_utc` with
+  /// `id`, oldest first. That message may be in another feed, so that every
+  /// room can be cut at the same point.
+  pub fn since(&self, created_at_utc: DateTime<Utc>, id: Uuid) -> &[Message] {
+    let start = self
+      .messages
+      .partition_point(|message| (message.created_at_utc, message.id) <= (created_at_utc, id));
+    &self.messages[start..]
+  }
+
+  /// Up to `limit` messages older than the message `id`, oldest first.
+  /// Returns `None` when `id` is not in this feed.
+  pub fn before(&self, id: &Uuid, limit: usize) -> Option<&[Message]> {
+    let created_at_utc = self.created_at_by_id.get(id)?;
+    let end = self.position((*created_at_utc, *id));
+    Some(&self.messages[end.saturating_sub(limit)..end])
+  }
+
+  /// A page of up to `limit` messages older than the message `before`, or of
+  /// the newest ones, oldest first, and whether there are older ones left.
+  /// Returns `None` when `before` is not in this feed.
+  pub fn page(&self, before: Option<&Uuid>, limit: usize) -> Option<(&[Message], bool)> {
+    let end = match before {
+      Some(id) => self.position((*self.created_at_by_id.get(id)?, *id)),
+      None => self.messages.len(),
+    };
+    let start = end.saturating_sub(limit);
+    Some((&self.messages[start..end], start > 0))
+  }
+
+  /// Messages newer than the message `id`, oldest first.
+  /// Returns `None` when `id` is not in this feed.
+  pub fn after(&self, id: &Uuid) -> Option<&[Message]> {
+    let created_at_utc = self.created_at_by_id.get(id)?;
+    let start = self.position((*created_at_utc, *id)) + 1;
+    Some(&self.messages[start..])
+  }
+
+  /// Replies in the thread of the message `parent_id`, oldest first.
+  pub fn replies(&self, parent_id: &Uuid) -> Vec<&Message> {
+    self
+      .replies_by_parent
+      .get(parent_id)
+      .map(|replies| replies.iter().map(|key| &self.messages[self.position(*key)]).collect())
+      .unwrap_or_default()
+  }
+
+  /// Messages matching `query`, newest first. Only the messages holding the
+  /// query's rarest word are looked at.
+  pub fn search<'a>(&'a self, query: &'a SearchQuery) -> impl Iterator<Item = &'a Message> + 'a {
+    // A word nobody used means nothing can match
+    let mut postings: Vec<&BTreeSet<(DateTime<Utc>, Uuid)>> = query
+      .words()
+      .map(|word| self.messages_by_word.get(word))
+      .collect::<Option<_>>()
+      .unwrap_or_default();
+    postings.sort_by_key(|keys| keys.len());
+    let rarest = postings.first().copied();
+    rarest
+      .into_iter()
+      .flat_map(|keys| keys.iter().rev())
+      .filter(move |key| postings[1..].iter().all(|keys| keys.contains(key)))
+      .map(move |key| &self.messages[self.position(*key)])
+      .filter(move |message| query.matches(&message.text))
+  }
+
+  fn index(&mut self, key: (DateTime<Utc>, Uuid), text: &str) {
+    for word in search::words(text) {
+      self.messages_by_word.entry(word).or_default().insert(key);
+    }
+  }
+
+  fn unindex(&mut self, key: (DateTime<Utc>, Uuid), text: &str) {
+    for word in search::words(text) {
+      if let Some(keys) = self.messages_by_word.get_mut(&word) {
+        keys.remove(&key);
+        if keys.is_empty() {
+          self.messages_by_word.remove(&word);
+        }
+      }
+    }
+  }
+
+  // Messages are ordered by creation time, ties broken by id
+  fn position(&self, key: (DateTime<Utc>, Uuid)) -> usize {
+    self
+      .messages
+      .partition_point(|message| (message.created_at_utc, message.id) < key)
+  }
 }
 
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::model::user::User;
+  use chrono::Duration;
+
+  #[test]
+  fn pages_backwards_from_cursor() {
+    let user = User::new(Uuid::new_v4(), "daolavi");
+    let room_id = Uuid::new_v4();
+    let now = Utc::now();
+    let mut feed = Feed::default();
+    // Added out of order on purpose
+    for (offset, text) in [(2, "c"), (0, "a"), (3, "d"), (1, "b")].iter() {
+      let created_at_utc = now + Duration::seconds(*offset);
+      feed.add_message(Message::new(Uuid::new_v4(), room_id, user.clone(), text, created_at_utc));
+    }
+    let texts = |messages: &[Message]| messages.iter().map(|m| m.text.clone()).collect::<Vec<_>>();
+
+    assert_eq!(texts(feed.latest(2)), vec!["c", "d"]);
+    assert_eq!(texts(feed.latest(10)), vec!["a", "b", "c", "d"]);
+
+    let cursor = feed.latest(2)[0].id;
+    assert_eq!(feed.get(&cursor).unwrap().text, "c");
+    assert_eq!(texts(feed.before(&cursor, 1).unwrap()), vec!["b"]);
+    assert_eq!(texts(feed.before(&cursor, 5).unwrap()), vec!["a", "b"]);
+    assert!(feed.before(&Uuid::new_v4(), 5).is_none());
+
+    let (page, has_more) = feed.page(Some(&cursor), 1).unwrap();
+    assert_eq!((texts(page), has_more), (vec![String::from("b")], true));
+    let (page, has_more) = feed.page(Some(&cursor), 2).unwrap();
+    assert_eq!((texts(page), has_more), (vec![String::from("a"), String::from("b")], false));
+    let oldest = feed.latest(10)[0].id;
+    let (page, has_more) = feed.page(Some(&oldest), 5).unwrap();
+    assert!(page.is_empty() && !has_more);
+    let (page, has_more) = feed.page(None, 0).unwrap();
+    assert!(page.is_empty() && has_more);
+  }
+
+  #[test]
+  fn cuts_messages_from_the_same_instant_by_id() {
+    let user = User::new(Uuid::new_v4(), "daolavi");
+    let room_id = Uuid::new_v4();
+    let now = Utc::now();
+    let mut feed = Feed::default();
+    for text in ["a", "b", "c"].iter() {
+      feed.add_message(Message::new(Uuid::new_v4(), room_id, user.clone(), text, now));
+    }
+
+    let middle = &feed.messages[1];
+    let since = feed.since(middle.created_at_utc, middle.id);
+    assert_eq!(since.len(), 1);
+    assert_eq!(since[0].id, feed.after(&middle.id).unwrap()[0].id);
+    assert_eq!(feed.since(now, Uuid::nil()).len(), 3);
+    assert!(feed.since(now, Uuid::from_u128(u128::MAX)).is_empty());
+  }
+
+  #[test]
+  fn indexes_replies_by_parent() {
+    let user = User::new(Uuid::new_v4(), "daolavi");
+    let room_id = Uuid::new_v4();
+    let now = Utc::now();
+    let mut feed = Feed::default();
+    let root = Message::new(Uuid::new_v4(), room_id, user.clone(), "root", now);
+    let root_id = root.id;
+    feed.add_message(root);
+    for (offset, text) in [(2, "second"), (1, "first")].iter() {
+      let reply = Message::new(Uuid::new_v4(), room_id, user.clone(), text, now + Duration::seconds(*offset));
+      feed.add_message(Message { parent_id: Some(root_id), ..reply });
+    }
+
+    let texts: Vec<&str> = feed.replies(&root_id).iter().map(|m| m.text.as_str()).collect();
+    assert_eq!(texts, vec!["first", "second"]);
+    assert_eq!(feed.get(&root_id).unwrap().reply_count, 2);
+    assert!(feed.replies(&Uuid::new_v4()).is_empty());
+  }
+}
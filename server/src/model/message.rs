@@ -1,22 +1,81 @@
 use super::user::User;
 use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use uuid::Uuid;
 
-#[derive(Debug, Clone)]
+/// Who reacted to a message, by emoji.
+pub type Reactions = BTreeMap<String, BTreeSet<Uuid>>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Message {
   pub id: Uuid,
+  pub room_id: Uuid,
   pub user: User,
   pub text: String,
   pub created_at_utc: DateTime<Utc>,
+  #[serde(default)]
+  pub edited_at_utc: Option<DateTime<Utc>>,
+  #[serde(default)]
+  pub deleted: bool,
+  #[serde(default)]
+  pub reactions: Reactions,
+  /// Root of the thread this message replies in.
+  #[serde(default)]
+  pub parent_id: Option<Uuid>,
+  /// Kept up to date by the feed, so it is not stored.
+  #[serde(skip)]
+  pub reply_count: usize,
+  /// Users addressed with `@name` when the message was posted.
+  #[serde(default)]
+  pub mentions: Vec<Uuid>,
 }
 
 impl Message {
-  pub fn new(id: Uuid, user: User, text: &str, created_at_utc: DateTime<Utc>) -> Self{
+  pub fn new(id: Uuid, room_id: Uuid, user: User, text: &str, created_at_utc: DateTime<Utc>) -> Self{
     Message {
       id,
+      room_id,
       user,
       text: String::from(text),
-      created_at_utc
+      created_at_utc,
+      edited_at_utc: None,
+      deleted: false,
+      reactions: Reactions::new(),
+      parent_id: None,
+      reply_count: 0,
+      mentions: Vec::new(),
     }
   }
-}
\ No newline at end of file
+
+  pub fn edit(&mut self, text: &str, edited_at_utc: DateTime<Utc>) {
+    self.text = String::from(text);
+    self.edited_at_utc = Some(edited_at_utc);
+  }
+
+  /// Keeps the message in place as a tombstone so history cursors stay valid.
+  pub fn delete(&mut self) {
+    self.text.clear();
+    self.reactions.clear();
+    self.deleted = true;
+  }
+
+  pub fn has_reacted(&self, emoji: &str, user_id: &Uuid) -> bool {
+    self.reactions.get(emoji).is_some_and(|users| users.contains(user_id))
+  }
+
+  pub fn react(&mut self, emoji: &str, user_id: Uuid) {
+    self.reactions.entry(String::from(emoji)).or_default().insert(user_id);
+  }
+
+  /// Forgets the emoji altogether once nobody reacts with it anymore.
+  pub fn unreact(&mut self, emoji: &str, user_id: &Uuid) {
+    if let Some(users) = self.reactions.get_mut(emoji) {
+      users.remove(user_id);
+      if users.is_empty() {
+        self.reactions.remove(emoji);
+      }
+    }
+  }
+}
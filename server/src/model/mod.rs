@@ -1,3 +1,9 @@
+pub mod account;
+pub mod ban;
+pub mod direct_message;
 pub mod feed;
 pub mod user;
-pub mod message;
\ No newline at end of file
+pub mod message;
+pub mod room;
+pub mod search;
+pub mod session;
@@ -0,0 +1,23 @@
+use std::collections::HashSet;
+
+use uuid::Uuid;
+
+pub struct Room {
+    pub id: Uuid,
+    pub name: String,
+    pub members: HashSet<Uuid>,
+}
+
+impl Room {
+    pub fn new(id: Uuid, name: &str) -> Self {
+        Room {
+            id,
+            name: String::from(name),
+            members: Default::default(),
+        }
+    }
+
+    pub fn is_member(&self, client_id: &Uuid) -> bool {
+        self.members.contains(client_id)
+    }
+}
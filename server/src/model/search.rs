@@ -0,0 +1,69 @@
+/// Lower-cased words of `text`, in order. Anything but letters and digits
+/// separates words.
+pub fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// A parsed search where every term has to match. A term is a single word,
+/// or a quoted phrase whose words have to follow each other.
+#[derive(Debug, PartialEq)]
+pub struct SearchQuery {
+    terms: Vec<Vec<String>>,
+}
+
+impl SearchQuery {
+    /// Returns `None` when the query holds no words at all.
+    pub fn parse(query: &str) -> Option<Self> {
+        let mut terms = Vec::new();
+        // Every other piece is quoted; an unclosed quote runs to the end
+        for (index, piece) in query.split('"').enumerate() {
+            if index % 2 == 1 {
+                let phrase: Vec<String> = words(piece).collect();
+                if !phrase.is_empty() {
+                    terms.push(phrase);
+                }
+            } else {
+                terms.extend(words(piece).map(|word| vec![word]));
+            }
+        }
+        if terms.is_empty() {
+            None
+        } else {
+            Some(SearchQuery { terms })
+        }
+    }
+
+    /// Every word the query needs, to look up in an index.
+    pub fn words(&self) -> impl Iterator<Item = &str> {
+        self.terms.iter().flatten().map(String::as_str)
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        let text: Vec<String> = words(text).collect();
+        self.terms.iter().all(|term| {
+            text.windows(term.len())
+                .any(|window| window == term.as_slice())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_words_and_phrases_ignoring_case() {
+        let query = SearchQuery::parse(r#"Deploy "green build""#).unwrap();
+        assert_eq!(
+            query.words().collect::<Vec<_>>(),
+            vec!["deploy", "green", "build"]
+        );
+        assert!(query.matches("deploy: the GREEN build is out"));
+        assert!(!query.matches("deploy the build, it is green"));
+        assert!(!query.matches("deployed the green build"));
+
+        assert!(SearchQuery::parse(r#"  "" ?! "#).is_none());
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use super::user::User;
+use uuid::Uuid;
+
+/// A dropped connection whose user can still come back with its token.
+#[derive(Debug, Clone)]
+pub struct SuspendedSession {
+    pub user: User,
+    pub room_ids: Vec<Uuid>,
+    /// Where the dropped connection came from, so address bans reach it.
+    pub address: Option<IpAddr>,
+    pub suspended_at: Instant,
+}
+
+pub enum Resumable {
+    Suspended(SuspendedSession),
+    /// The old connection has not been noticed as dropped yet.
+    Live(Uuid),
+}
+
+/// Resume tokens of live connections and of the ones waiting to be resumed.
+#[derive(Default)]
+pub struct Sessions {
+    live: HashMap<Uuid, String>,
+    suspended: HashMap<String, SuspendedSession>,
+}
+
+impl Sessions {
+    /// Issues a fresh token for `client_id`, replacing any previous one.
+    pub fn issue(&mut self, client_id: Uuid) -> String {
+        let token = Uuid::new_v4().to_simple().to_string();
+        self.live.insert(client_id, token.clone());
+        token
+    }
+
+    /// Keeps the session of `client_id` resumable; false if it had no token.
+    pub fn suspend(
+        &mut self,
+        client_id: Uuid,
+        user: User,
+        room_ids: Vec<Uuid>,
+        address: Option<IpAddr>,
+    ) -> bool {
+        match self.live.remove(&client_id) {
+            Some(token) => {
+                let session = SuspendedSession {
+                    user,
+                    room_ids,
+                    address,
+                    suspended_at: Instant::now(),
+                };
+                self.suspended.insert(token, session);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn take(&mut self, token: &str) -> Option<Resumable> {
+        if let Some(session) = self.suspended.remove(token) {
+            return Some(Resumable::Suspended(session));
+        }
+        let client_id = self
+            .live
+            .iter()
+            .find(|(_, live_token)| live_token.as_str() == token)
+            .map(|(client_id, _)| *client_id)?;
+        self.live.remove(&client_id);
+        Some(Resumable::Live(client_id))
+    }
+
+    /// Drops the sessions suspended for longer than `grace_period`.
+    pub fn expire(&mut self, grace_period: Duration) -> Vec<SuspendedSession> {
+        let expired: Vec<String> = self
+            .suspended
+            .iter()
+            .filter(|(_, session)| session.suspended_at.elapsed() >= grace_period)
+            .map(|(token, _)| token.clone())
+            .collect();
+        expired
+            .iter()
+            .filter_map(|token| self.suspended.remove(token))
+            .collect()
+    }
+
+    /// Drops the tokens of `client_ids` and every session suspended for
+    /// `user_id`, so that none of them can be resumed.
+    pub fn revoke(&mut self, user_id: &Uuid, client_ids: &[Uuid]) {
+        for client_id in client_ids {
+            self.live.remove(client_id);
+        }
+        self.suspended
+            .retain(|_, session| session.user.id != *user_id);
+    }
+
+    /// Drops every session suspended from `address`.
+    pub fn revoke_address(&mut self, address: &IpAddr) {
+        self.suspended
+            .retain(|_, session| session.address.as_ref() != Some(address));
+    }
+
+    pub fn is_suspended(&self, user_id: &Uuid) -> bool {
+        self.suspended
+            .values()
+            .any(|session| session.user.id == *user_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_resume_once_and_expire() {
+        let mut sessions = Sessions::default();
+        let user = User::new(Uuid::new_v4(), "daolavi");
+        let client_id = Uuid::new_v4();
+
+        let token = sessions.issue(client_id);
+        assert!(matches!(sessions.take(&token), Some(Resumable::Live(id)) if id == client_id));
+        assert!(sessions.take(&token).is_none());
+
+        let token = sessions.issue(client_id);
+        assert!(sessions.suspend(client_id, user.clone(), vec![], None));
+        assert!(!sessions.suspend(client_id, user.clone(), vec![], None));
+        assert!(sessions.is_suspended(&user.id));
+        assert!(sessions.expire(Duration::from_secs(60)).is_empty());
+        assert!(matches!(
+            sessions.take(&token),
+            Some(Resumable::Suspended(_))
+        ));
+
+        let token = sessions.issue(client_id);
+        sessions.suspend(client_id, user.clone(), vec![], None);
+        assert_eq!(sessions.expire(Duration::from_secs(0)).len(), 1);
+        assert!(sessions.take(&token).is_none());
+
+        let address: IpAddr = "203.0.113.7".parse().unwrap();
+        let token = sessions.issue(client_id);
+        sessions.suspend(client_id, user.clone(), vec![], Some(address));
+        sessions.revoke_address(&"203.0.113.8".parse().unwrap());
+        assert!(sessions.is_suspended(&user.id));
+        sessions.revoke_address(&address);
+        assert!(sessions.take(&token).is_none());
+    }
+}
@@ -1,9 +1,21 @@
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Role {
+    #[default]
+    Member,
+    /// May mute, kick and ban other users.
+    Moderator,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub id: Uuid,
     pub name: String,
+    #[serde(default)]
+    pub role: Role,
 }
 
 impl User {
@@ -11,6 +23,7 @@ impl User {
         User {
             id,
             name: String::from(name),
+            role: Role::Member,
         }
     }
 }
@@ -0,0 +1,322 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    result,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use futures::{stream, Stream};
+use serde::Deserialize;
+use tokio::sync::Notify;
+
+use crate::protocol::response::{ResponseData, ResponseMessage};
+
+/// Responses waiting to be written to a client before its buffer is full.
+pub const OUTBOX_CAPACITY: usize = 256;
+/// Close code sent to a client disconnected for falling behind.
+pub const SLOW_CONSUMER_CLOSE_CODE: u16 = 1008;
+/// Close code sent to a client an operator disconnected.
+pub const KICKED_CLOSE_CODE: u16 = 4000;
+/// Close code sent to a client that kept going over its rate limits.
+pub const RATE_LIMITED_CLOSE_CODE: u16 = 1008;
+/// Close code sent to a client that kept sending frames that are not requests.
+pub const PROTOCOL_ERROR_CLOSE_CODE: u16 = 1002;
+
+/// What to do when a client's outbound buffer is full.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Make room by discarding the oldest queued response.
+    DropOldest,
+    /// Close the connection; the client can resume and replay what it missed.
+    #[default]
+    Disconnect,
+    /// Never queue more than one `Alive` tick and evict it first when full,
+    /// disconnecting only when there is no tick to give up.
+    CoalesceAlive,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(policy: &str) -> result::Result<Self, Self::Err> {
+        match policy {
+            "drop_oldest" => Ok(OverflowPolicy::DropOldest),
+            "disconnect" => Ok(OverflowPolicy::Disconnect),
+            "coalesce_alive" => Ok(OverflowPolicy::CoalesceAlive),
+            _ => Err(String::from(
+                "expected drop_oldest, disconnect or coalesce_alive",
+            )),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Delivery {
+    Queued,
+    DroppedOldest,
+    Coalesced,
+    Disconnected,
+    /// The client is already gone or was disconnected before.
+    Closed,
+}
+
+/// How many times each overflow action was taken, across all clients.
+#[derive(Debug, Default)]
+pub struct OverflowCounters {
+    pub dropped_oldest: AtomicU64,
+    pub coalesced: AtomicU64,
+    pub disconnected: AtomicU64,
+}
+
+impl OverflowCounters {
+    pub fn count(&self, delivery: &Delivery) {
+        let counter = match delivery {
+            Delivery::DroppedOldest => &self.dropped_oldest,
+            Delivery::Coalesced => &self.coalesced,
+            Delivery::Disconnected => &self.disconnected,
+            Delivery::Queued | Delivery::Closed => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// A close frame is written at most once per connection, so it is not worth
+// boxing responses to shrink it
+#[allow(clippy::large_enum_variant)]
+pub enum Outbound {
+    Response(ResponseMessage),
+    Close(u16, &'static str),
+}
+
+#[derive(Default)]
+struct Queue {
+    responses: VecDeque<ResponseMessage>,
+    closed: bool,
+    overflowed: bool,
+    close_frame: Option<(u16, &'static str)>,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    notify: Notify,
+    fell_behind: Notify,
+}
+
+/// Sending half of a client's bounded outbound buffer.
+pub struct Outbox {
+    shared: Arc<Shared>,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+/// Receiving half, drained by the connection's writer.
+pub struct Inbox {
+    shared: Arc<Shared>,
+}
+
+pub fn channel(capacity: usize, policy: OverflowPolicy) -> (Outbox, Inbox) {
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(Queue::default()),
+        notify: Notify::new(),
+        fell_behind: Notify::new(),
+    });
+    let outbox = Outbox {
+        shared: shared.clone(),
+        capacity,
+        policy,
+    };
+    (outbox, Inbox { shared })
+}
+
+impl Outbox {
+    pub fn send(&self, response: impl Into<ResponseMessage>) -> Delivery {
+        let delivery = self.push(response.into());
+        if delivery != Delivery::Closed {
+            self.shared.notify.notify_one();
+        }
+        if delivery == Delivery::Disconnected {
+            self.shared.fell_behind.notify_one();
+        }
+        delivery
+    }
+
+    /// Lets the client read what is already queued, then closes its
+    /// connection with `code`.
+    pub fn close(&self, code: u16, reason: &'static str) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        if !queue.closed && !queue.overflowed {
+            queue.closed = true;
+            queue.close_frame = Some((code, reason));
+        }
+        drop(queue);
+        self.shared.notify.notify_one();
+    }
+
+    fn push(&self, response_message: ResponseMessage) -> Delivery {
+        let mut queue = self.shared.queue.lock().unwrap();
+        if queue.closed || queue.overflowed {
+            return Delivery::Closed;
+        }
+
+        let is_alive = response_message.response_data == ResponseData::Alive;
+        let alive_position = |queue: &Queue| {
+            queue
+                .responses
+                .iter()
+                .position(|queued| queued.response_data == ResponseData::Alive)
+        };
+        if self.policy == OverflowPolicy::CoalesceAlive
+            && is_alive
+            && alive_position(&queue).is_some()
+        {
+            return Delivery::Coalesced;
+        }
+        if queue.responses.len() < self.capacity {
+            queue.responses.push_back(response_message);
+            return Delivery::Queued;
+        }
+
+        match self.policy {
+            OverflowPolicy::DropOldest => {
+                queue.responses.pop_front();
+                queue.responses.push_back(response_message);
+                Delivery::DroppedOldest
+            }
+            OverflowPolicy::CoalesceAlive if is_alive => Delivery::Coalesced,
+            OverflowPolicy::CoalesceAlive if alive_position(&queue).is_some() => {
+                let position = alive_position(&queue).unwrap();
+                queue.responses.remove(position);
+                queue.responses.push_back(response_message);
+                Delivery::Coalesced
+            }
+            OverflowPolicy::Disconnect | OverflowPolicy::CoalesceAlive => {
+                queue.responses.clear();
+                queue.overflowed = true;
+                queue.close_frame = Some((SLOW_CONSUMER_CLOSE_CODE, "slow consumer"));
+                Delivery::Disconnected
+            }
+        }
+    }
+}
+
+impl Drop for Outbox {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().closed = true;
+        self.shared.notify.notify_one();
+    }
+}
+
+impl Inbox {
+    /// The next response, or `None` once the outbox is gone or overflowed.
+    pub async fn recv(&mut self) -> Option<ResponseData> {
+        self.recv_message()
+            .await
+            .map(|response_message| response_message.response_data)
+    }
+
+    /// Like `recv`, keeping the id of the request the response answers.
+    pub async fn recv_message(&mut self) -> Option<ResponseMessage> {
+        loop {
+            match self.try_recv_message() {
+                Some(response_message) => return Some(response_message),
+                None if self.is_closed() => return None,
+                None => self.shared.notify.notified().await,
+            }
+        }
+    }
+
+    pub fn try_recv(&mut self) -> Option<ResponseData> {
+        self.try_recv_message()
+            .map(|response_message| response_message.response_data)
+    }
+
+    fn try_recv_message(&mut self) -> Option<ResponseMessage> {
+        self.shared.queue.lock().unwrap().responses.pop_front()
+    }
+
+    pub fn overflowed(&self) -> bool {
+        self.shared.queue.lock().unwrap().overflowed
+    }
+
+    /// Resolves once the client overflowed its buffer. Unlike the stream,
+    /// this does not wait for the writer to get through what it was sending.
+    pub fn fell_behind(&self) -> impl Future<Output = ()> {
+        let shared = self.shared.clone();
+        async move {
+            loop {
+                let overflowed = shared.queue.lock().unwrap().overflowed;
+                if overflowed {
+                    return;
+                }
+                shared.fell_behind.notified().await;
+            }
+        }
+    }
+
+    fn close_frame(&self) -> Option<(u16, &'static str)> {
+        self.shared.queue.lock().unwrap().close_frame
+    }
+
+    fn is_closed(&self) -> bool {
+        let queue = self.shared.queue.lock().unwrap();
+        queue.closed || queue.overflowed
+    }
+
+    /// Queued responses, followed by a close frame if the client fell behind
+    /// or was closed on purpose.
+    pub fn into_stream(self) -> impl Stream<Item = Outbound> {
+        stream::unfold(Some(self), |inbox| async {
+            let mut inbox = inbox?;
+            match inbox.recv_message().await {
+                Some(response_message) => Some((Outbound::Response(response_message), Some(inbox))),
+                None => inbox
+                    .close_frame()
+                    .map(|(code, reason)| (Outbound::Close(code, reason), None)),
+            }
+        })
+    }
+}
+
+impl Drop for Inbox {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().closed = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::response::{ErrorType, ResponseData};
+
+    fn error() -> ResponseData {
+        ResponseData::Error(ErrorType::NotJoined)
+    }
+
+    #[test]
+    fn full_outboxes_follow_their_policy() {
+        let (outbox, mut inbox) = channel(2, OverflowPolicy::DropOldest);
+        assert_eq!(outbox.send(ResponseData::Alive), Delivery::Queued);
+        assert_eq!(outbox.send(error()), Delivery::Queued);
+        assert_eq!(outbox.send(error()), Delivery::DroppedOldest);
+        assert_eq!(inbox.try_recv(), Some(error()));
+
+        let (outbox, inbox) = channel(1, OverflowPolicy::Disconnect);
+        assert_eq!(outbox.send(error()), Delivery::Queued);
+        assert_eq!(outbox.send(error()), Delivery::Disconnected);
+        assert_eq!(outbox.send(error()), Delivery::Closed);
+        assert!(inbox.overflowed());
+
+        let (outbox, mut inbox) = channel(2, OverflowPolicy::CoalesceAlive);
+        assert_eq!(outbox.send(ResponseData::Alive), Delivery::Queued);
+        assert_eq!(outbox.send(ResponseData::Alive), Delivery::Coalesced);
+        assert_eq!(outbox.send(error()), Delivery::Queued);
+        assert_eq!(outbox.send(error()), Delivery::Coalesced);
+        assert_eq!(outbox.send(error()), Delivery::Disconnected);
+        assert_eq!(inbox.try_recv(), None);
+    }
+}
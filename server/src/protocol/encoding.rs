@@ -0,0 +1,169 @@
+use serde::{de::DeserializeOwned, Serialize};
+use warp::ws::Message;
+
+use super::response::ErrorType;
+use crate::error::{Error, Result};
+
+/// How requests and responses are written in frames, picked from the
+/// `Sec-WebSocket-Protocol` the client offers on upgrade. JSON goes in text
+/// frames and is used when nothing is offered; the others go in binary ones.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Encoding {
+    #[default]
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl Encoding {
+    pub fn from_subprotocol(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(Encoding::Json),
+            "msgpack" => Some(Encoding::MessagePack),
+            "cbor" => Some(Encoding::Cbor),
+            _ => None,
+        }
+    }
+
+    /// The first encoding named in a comma-separated list of subprotocols.
+    pub fn from_offer(offer: &str) -> Option<Self> {
+        offer
+            .split(',')
+            .map(str::trim)
+            .find_map(Self::from_subprotocol)
+    }
+
+    pub fn subprotocol(self) -> &'static str {
+        match self {
+            Encoding::Json => "json",
+            Encoding::MessagePack => "msgpack",
+            Encoding::Cbor => "cbor",
+        }
+    }
+
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Message> {
+        match self {
+            Encoding::Json => Ok(Message::text(serde_json::to_string(value)?)),
+            // Named fields, so that flattened envelopes read back as maps
+            Encoding::MessagePack => rmp_serde::to_vec_named(value)
+                .map(Message::binary)
+                .map_err(|err| Error::Encoding(err.to_string())),
+            Encoding::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::ser::into_writer(value, &mut bytes)
+                    .map_err(|err| Error::Encoding(err.to_string()))?;
+                Ok(Message::binary(bytes))
+            }
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(
+        self,
+        message: &Message,
+    ) -> std::result::Result<T, ErrorType> {
+        match self {
+            Encoding::Json => {
+                let text = message
+                    .to_str()
+                    .map_err(|()| ErrorType::invalid_request("expected a text frame"))?;
+                serde_json::from_str(text).map_err(|err| ErrorType::from(&err))
+            }
+            _ if !message.is_binary() => Err(ErrorType::invalid_request("expected a binary frame")),
+            Encoding::MessagePack => rmp_serde::from_slice(message.as_bytes())
+                .map_err(|err| ErrorType::invalid_request(&err.to_string())),
+            Encoding::Cbor => ciborium::de::from_reader(message.as_bytes())
+                .map_err(|err| ErrorType::invalid_request(&err.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        model::{ban::Ban, message::Message as ChatMessage, user::User},
+        protocol::{
+            request::{BanRequestData, PostMessageRequestData, RequestData, RequestEnvelope},
+            response::{
+                MessageResponse, PostedResponse, ResponseData, ResponseMessage, UserBannedResponse,
+            },
+        },
+    };
+
+    #[test]
+    fn requests_and_responses_round_trip_in_every_encoding() {
+        let requests = vec![
+            RequestEnvelope {
+                request_id: Some(String::from("1")),
+                request_data: RequestData::PostMessage(PostMessageRequestData {
+                    room_id: Uuid::new_v4(),
+                    text: String::from("Hello"),
+                    reply_to: Some(Uuid::new_v4()),
+                }),
+            },
+            RequestEnvelope {
+                request_id: None,
+                request_data: RequestData::ListRooms,
+            },
+            RequestEnvelope {
+                request_id: None,
+                request_data: RequestData::Ban(BanRequestData {
+                    user_id: None,
+                    name: None,
+                    ip: Some("2001:db8::1".parse().unwrap()),
+                }),
+            },
+        ];
+        let user = User::new(Uuid::new_v4(), "daolavi");
+        let mut message = ChatMessage::new(
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            user.clone(),
+            "Hi",
+            Utc::now(),
+        );
+        message.react("👍", user.id);
+        let responses = vec![
+            ResponseMessage::new(
+                ResponseData::Posted(PostedResponse::new(MessageResponse::from(&message))),
+                Some(String::from("1")),
+            ),
+            ResponseMessage::from(ResponseData::Alive),
+            ResponseMessage::from(ResponseData::UserBanned(UserBannedResponse::new(
+                Ban::Address("203.0.113.7".parse().unwrap()),
+            ))),
+            ResponseMessage::from(ResponseData::Error(ErrorType::RateLimited {
+                retry_after_ms: 250,
+            })),
+        ];
+
+        for encoding in &[Encoding::Json, Encoding::MessagePack, Encoding::Cbor] {
+            for request in &requests {
+                let frame = encoding.encode(request).unwrap();
+                assert_eq!(frame.is_binary(), *encoding != Encoding::Json);
+                assert_eq!(
+                    encoding.decode::<RequestEnvelope>(&frame),
+                    Ok(request.clone())
+                );
+            }
+            for response in &responses {
+                let frame = encoding.encode(response).unwrap();
+                assert_eq!(
+                    encoding.decode::<ResponseMessage>(&frame),
+                    Ok(response.clone())
+                );
+            }
+        }
+
+        let frame = Encoding::Json.encode(&requests[1]).unwrap();
+        assert!(Encoding::Cbor.decode::<RequestEnvelope>(&frame).is_err());
+        assert_eq!(
+            Encoding::from_offer("chat, cbor, msgpack"),
+            Some(Encoding::Cbor)
+        );
+        assert_eq!(Encoding::from_offer("chat"), None);
+    }
+}
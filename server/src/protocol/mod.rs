@@ -1,2 +1,4 @@
+pub mod encoding;
 pub mod request;
-pub mod response;
\ No newline at end of file
+pub mod response;
+pub mod version;
@@ -1,31 +1,273 @@
+use std::net::IpAddr;
+
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 #[derive(Debug, Clone)]
 pub struct RequestMessage {
     pub client_id: Uuid,
+    /// Echoed on the responses to the request, see `RequestEnvelope`.
+    pub request_id: Option<String>,
     pub request_data: RequestData,
 }
 
 impl RequestMessage {
     pub fn new(client_id: Uuid, request_data: RequestData) -> Self {
-        RequestMessage { client_id, request_data }
+        RequestMessage { client_id, request_id: None, request_data }
+    }
+
+    pub fn with_request_id(self, request_id: Option<String>) -> Self {
+        RequestMessage { request_id, ..self }
     }
 }
 
+/// A request as sent on the wire. Clients may tag it with a `requestId` of
+/// their choosing to tell which `Posted`, `Joined` or `Error` response
+/// answers it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestEnvelope {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(flatten)]
+    pub request_data: RequestData,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "type", content = "payload", rename_all = "camelCase")]
 pub enum RequestData {
     Join(JoinRequestData),
     PostMessage(PostMessageRequestData),
+    CreateRoom(CreateRoomRequestData),
+    ListRooms,
+    JoinRoom(RoomRequestData),
+    LeaveRoom(RoomRequestData),
+    DirectMessage(DirectMessageRequestData),
+    FetchDirectMessages(FetchDirectMessagesRequestData),
+    FetchHistory(FetchHistoryRequestData),
+    EditMessage(EditMessageRequestData),
+    DeleteMessage(DeleteMessageRequestData),
+    Register(CredentialsRequestData),
+    Login(CredentialsRequestData),
+    Resume(ResumeRequestData),
+    StartTyping(RoomRequestData),
+    StopTyping(RoomRequestData),
+    MarkRead(MarkReadRequestData),
+    React(ReactionRequestData),
+    Unreact(ReactionRequestData),
+    FetchThread(FetchThreadRequestData),
+    FetchMentions,
+    Search(SearchRequestData),
+    Mute(MuteRequestData),
+    Kick(KickRequestData),
+    Ban(BanRequestData),
+    /// Opens the conversation with the newest protocol version the client
+    /// speaks; answered with `Welcome`.
+    Hello(HelloRequestData),
+}
+
+impl RequestData {
+    /// Every `kind` there is, for checking rate limits against.
+    pub const KINDS: [&'static str; 26] = [
+        "join",
+        "postMessage",
+        "createRoom",
+        "listRooms",
+        "joinRoom",
+        "leaveRoom",
+        "directMessage",
+        "fetchDirectMessages",
+        "fetchHistory",
+        "editMessage",
+        "deleteMessage",
+        "register",
+        "login",
+        "resume",
+        "startTyping",
+        "stopTyping",
+        "markRead",
+        "react",
+        "unreact",
+        "fetchThread",
+        "fetchMentions",
+        "search",
+        "mute",
+        "kick",
+        "ban",
+        "hello",
+    ];
+
+    /// The `type` tag of the request, which rate limits are configured by.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RequestData::Join(_) => "join",
+            RequestData::PostMessage(_) => "postMessage",
+            RequestData::CreateRoom(_) => "createRoom",
+            RequestData::ListRooms => "listRooms",
+            RequestData::JoinRoom(_) => "joinRoom",
+            RequestData::LeaveRoom(_) => "leaveRoom",
+            RequestData::DirectMessage(_) => "directMessage",
+            RequestData::FetchDirectMessages(_) => "fetchDirectMessages",
+            RequestData::FetchHistory(_) => "fetchHistory",
+            RequestData::EditMessage(_) => "editMessage",
+            RequestData::DeleteMessage(_) => "deleteMessage",
+            RequestData::Register(_) => "register",
+            RequestData::Login(_) => "login",
+            RequestData::Resume(_) => "resume",
+            RequestData::StartTyping(_) => "startTyping",
+            RequestData::StopTyping(_) => "stopTyping",
+            RequestData::MarkRead(_) => "markRead",
+            RequestData::React(_) => "react",
+            RequestData::Unreact(_) => "unreact",
+            RequestData::FetchThread(_) => "fetchThread",
+            RequestData::FetchMentions => "fetchMentions",
+            RequestData::Search(_) => "search",
+            RequestData::Mute(_) => "mute",
+            RequestData::Kick(_) => "kick",
+            RequestData::Ban(_) => "ban",
+            RequestData::Hello(_) => "hello",
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct JoinRequestData {
+    /// May be left empty on authenticated connections to use the token's name.
+    #[serde(default)]
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CredentialsRequestData {
     pub name: String,
+    pub password: String,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct PostMessageRequestData {
+    pub room_id: Uuid,
+    pub text: String,
+    /// Posts the message as a reply in the thread of this message.
+    #[serde(default)]
+    pub reply_to: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CreateRoomRequestData {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomRequestData {
+    pub room_id: Uuid,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DirectMessageRequestData {
+    pub to: Uuid,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchDirectMessagesRequestData {
+    pub user_id: Uuid,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchHistoryRequestData {
+    pub room_id: Uuid,
+    pub before: Option<Uuid>,
+    pub limit: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EditMessageRequestData {
+    pub id: Uuid,
     pub text: String,
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeleteMessageRequestData {
+    pub id: Uuid,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResumeRequestData {
+    pub token: String,
+    pub last_seen_message_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarkReadRequestData {
+    pub message_id: Uuid,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReactionRequestData {
+    pub message_id: Uuid,
+    pub emoji: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchThreadRequestData {
+    pub root_id: Uuid,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchRequestData {
+    /// Words to find, with `"quoted phrases"` matched as a whole.
+    pub query: String,
+    pub from_user: Option<Uuid>,
+    pub before: Option<DateTime<Utc>>,
+    pub after: Option<DateTime<Utc>>,
+    pub limit: usize,
+}
+
+/// Exactly one of `user_id`, for a user online now, and `name` names who
+/// is muted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MuteRequestData {
+    #[serde(default)]
+    pub user_id: Option<Uuid>,
+    #[serde(default)]
+    pub name: Option<String>,
+    /// 0 lifts the mute.
+    pub duration_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KickRequestData {
+    pub user_id: Uuid,
+}
+
+/// Exactly one of the fields names who is banned.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BanRequestData {
+    #[serde(default)]
+    pub user_id: Option<Uuid>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default, with = "crate::model::ban::address_text::optional")]
+    pub ip: Option<IpAddr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HelloRequestData {
+    pub protocol_version: u32,
+    /// Optional features the client understands.
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+}
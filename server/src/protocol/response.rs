@@ -2,19 +2,38 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Clone, Debug)]
+use crate::model::{
+    ban::Ban,
+    direct_message::DirectMessage,
+    message::{Message, Reactions},
+    room::Room,
+    user::{Role, User},
+};
+
+/// A response as sent on the wire, carrying the `requestId` of the request
+/// it answers when there is one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct ResponseMessage {
-    pub client_id: Uuid,
-    pub response_data: ResponseData,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub request_id: Option<String>,
+  #[serde(flatten)]
+  pub response_data: ResponseData,
 }
 
 impl ResponseMessage {
-    pub fn new(client_id: Uuid, response_data: ResponseData) -> Self {
-        ResponseMessage {
-            client_id,
-            response_data,
-        }
+  pub fn new(response_data: ResponseData, request_id: Option<String>) -> Self {
+    ResponseMessage {
+      request_id,
+      response_data
     }
+  }
+}
+
+impl From<ResponseData> for ResponseMessage {
+  fn from(response_data: ResponseData) -> Self {
+    ResponseMessage::new(response_data, None)
+  }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -27,6 +46,32 @@ pub enum ResponseData {
     UserLeft(UserLeftResponse),
     Posted(PostedResponse),
     UserPosted(PostedResponse),
+    RoomCreated(RoomResponse),
+    Rooms(RoomsResponse),
+    RoomJoined(RoomJoinedResponse),
+    UserJoinedRoom(UserJoinedRoomResponse),
+    RoomLeft(RoomLeftResponse),
+    UserLeftRoom(UserLeftRoomResponse),
+    DirectMessageSent(DirectMessageResponse),
+    DirectMessageReceived(DirectMessageResponse),
+    DirectMessages(DirectMessagesResponse),
+    History(HistoryResponse),
+    MessageEdited(PostedResponse),
+    MessageDeleted(MessageDeletedResponse),
+    Resumed(ResumedResponse),
+    UserTyping(UserTypingResponse),
+    ReadReceipt(ReadReceiptResponse),
+    ReactionAdded(ReactionResponse),
+    ReactionRemoved(ReactionResponse),
+    Thread(ThreadResponse),
+    Mentioned(PostedResponse),
+    Mentions(MentionsResponse),
+    SearchResults(SearchResultsResponse),
+    Announcement(AnnouncementResponse),
+    UserMuted(UserMutedResponse),
+    UserKicked(UserLeftResponse),
+    UserBanned(UserBannedResponse),
+    Welcome(WelcomeResponse),
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -68,30 +113,229 @@ impl UserLeftResponse {
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct JoinedResponse {
     pub user: UserResponse,
     pub other_users: Vec<UserResponse>,
+    pub room: RoomResponse,
     pub messages: Vec<MessageResponse>,
+    pub resume_token: String,
+    /// Messages by other users in `room` after the user's last read one.
+    pub unread_count: usize,
 }
 
 impl JoinedResponse {
     pub fn new(
         user: UserResponse,
         other_users: Vec<UserResponse>,
+        room: RoomResponse,
         messages: Vec<MessageResponse>,
+        resume_token: &str,
+        unread_count: usize,
     ) -> Self {
         JoinedResponse {
             user,
             other_users,
+            room,
+            messages,
+            resume_token: String::from(resume_token),
+            unread_count,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResumedResponse {
+    pub user: UserResponse,
+    pub other_users: Vec<UserResponse>,
+    pub rooms: Vec<RoomResponse>,
+    /// Messages posted in those rooms while the client was away, as they
+    /// stand now: edited, deleted and reacted to. Changes made meanwhile to
+    /// messages the client had already seen are not replayed, so clients
+    /// that show older messages fetch them again with `FetchHistory`.
+    pub messages: Vec<MessageResponse>,
+    /// Rooms where more was missed than one history page. The rest is
+    /// fetched with `FetchHistory` from the oldest message replayed there.
+    pub truncated_room_ids: Vec<Uuid>,
+    pub resume_token: String,
+}
+
+impl ResumedResponse {
+    pub fn new(
+        user: UserResponse,
+        other_users: Vec<UserResponse>,
+        rooms: Vec<RoomResponse>,
+        messages: Vec<MessageResponse>,
+        truncated_room_ids: Vec<Uuid>,
+        resume_token: &str,
+    ) -> Self {
+        ResumedResponse {
+            user,
+            other_users,
+            rooms,
+            messages,
+            truncated_room_ids,
+            resume_token: String::from(resume_token),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomResponse {
+    pub id: Uuid,
+    pub name: String,
+    pub member_count: usize,
+}
+
+impl RoomResponse {
+    pub fn new(id: Uuid, name: &str, member_count: usize) -> Self {
+        RoomResponse {
+            id,
+            name: String::from(name),
+            member_count,
+        }
+    }
+}
+
+impl From<&Room> for RoomResponse {
+    fn from(room: &Room) -> Self {
+        RoomResponse::new(room.id, &room.name, room.members.len())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoomsResponse {
+    pub rooms: Vec<RoomResponse>,
+}
+
+impl RoomsResponse {
+    pub fn new(rooms: Vec<RoomResponse>) -> Self {
+        RoomsResponse { rooms }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoomJoinedResponse {
+    pub room: RoomResponse,
+    pub users: Vec<UserResponse>,
+    pub messages: Vec<MessageResponse>,
+}
+
+impl RoomJoinedResponse {
+    pub fn new(
+        room: RoomResponse,
+        users: Vec<UserResponse>,
+        messages: Vec<MessageResponse>,
+    ) -> Self {
+        RoomJoinedResponse {
+            room,
+            users,
             messages,
         }
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserJoinedRoomResponse {
+    pub room_id: Uuid,
+    pub user: UserResponse,
+}
+
+impl UserJoinedRoomResponse {
+    pub fn new(room_id: Uuid, user: UserResponse) -> Self {
+        UserJoinedRoomResponse { room_id, user }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomLeftResponse {
+    pub room_id: Uuid,
+}
+
+impl RoomLeftResponse {
+    pub fn new(room_id: Uuid) -> Self {
+        RoomLeftResponse { room_id }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserLeftRoomResponse {
+    pub room_id: Uuid,
+    pub user_id: Uuid,
+}
+
+impl UserLeftRoomResponse {
+    pub fn new(room_id: Uuid, user_id: Uuid) -> Self {
+        UserLeftRoomResponse { room_id, user_id }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserTypingResponse {
+    pub room_id: Uuid,
+    pub user_id: Uuid,
+    pub typing: bool,
+}
+
+impl UserTypingResponse {
+    pub fn new(room_id: Uuid, user_id: Uuid, typing: bool) -> Self {
+        UserTypingResponse {
+            room_id,
+            user_id,
+            typing,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadReceiptResponse {
+    pub room_id: Uuid,
+    pub user_id: Uuid,
+    pub message_id: Uuid,
+}
+
+impl ReadReceiptResponse {
+    pub fn new(room_id: Uuid, user_id: Uuid, message_id: Uuid) -> Self {
+        ReadReceiptResponse {
+            room_id,
+            user_id,
+            message_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReactionResponse {
+    pub room_id: Uuid,
+    pub message_id: Uuid,
+    pub user_id: Uuid,
+    pub emoji: String,
+}
+
+impl ReactionResponse {
+    pub fn new(room_id: Uuid, message_id: Uuid, user_id: Uuid, emoji: &str) -> Self {
+        ReactionResponse {
+            room_id,
+            message_id,
+            user_id,
+            emoji: String::from(emoji),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct UserResponse {
     pub id: Uuid,
     pub name: String,
+    pub role: Role,
 }
 
 impl UserResponse {
@@ -99,6 +343,16 @@ impl UserResponse {
         UserResponse {
             id,
             name: String::from(name),
+            role: Role::Member,
+        }
+    }
+}
+
+impl From<&User> for UserResponse {
+    fn from(user: &User) -> Self {
+        UserResponse {
+            role: user.role,
+            ..UserResponse::new(user.id, &user.name)
         }
     }
 }
@@ -107,27 +361,309 @@ impl UserResponse {
 #[serde(rename_all = "camelCase")]
 pub struct MessageResponse {
     pub id: Uuid,
+    pub room_id: Uuid,
     pub user: UserResponse,
     pub text: String,
     pub created_at_utc: DateTime<Utc>,
+    pub edited_at_utc: Option<DateTime<Utc>>,
+    pub deleted: bool,
+    pub reactions: Reactions,
+    pub parent_id: Option<Uuid>,
+    pub reply_count: usize,
+    pub mentions: Vec<Uuid>,
 }
 
 impl MessageResponse {
-    pub fn new(id: Uuid, user: UserResponse, text: &str, created_at_utc: DateTime<Utc>) -> Self {
+    pub fn new(
+        id: Uuid,
+        room_id: Uuid,
+        user: UserResponse,
+        text: &str,
+        created_at_utc: DateTime<Utc>,
+    ) -> Self {
         MessageResponse {
             id,
+            room_id,
             user,
             text: String::from(text),
             created_at_utc,
+            edited_at_utc: None,
+            deleted: false,
+            reactions: Reactions::new(),
+            parent_id: None,
+            reply_count: 0,
+            mentions: Vec::new(),
+        }
+    }
+}
+
+impl From<&Message> for MessageResponse {
+    fn from(message: &Message) -> Self {
+        MessageResponse {
+            edited_at_utc: message.edited_at_utc,
+            deleted: message.deleted,
+            reactions: message.reactions.clone(),
+            parent_id: message.parent_id,
+            reply_count: message.reply_count,
+            mentions: message.mentions.clone(),
+            ..MessageResponse::new(
+                message.id,
+                message.room_id,
+                UserResponse::from(&message.user),
+                &message.text,
+                message.created_at_utc,
+            )
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageDeletedResponse {
+    pub room_id: Uuid,
+    pub message_id: Uuid,
+}
+
+impl MessageDeletedResponse {
+    pub fn new(room_id: Uuid, message_id: Uuid) -> Self {
+        MessageDeletedResponse {
+            room_id,
+            message_id,
         }
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectMessageResponse {
+    pub id: Uuid,
+    pub from: UserResponse,
+    pub to: UserResponse,
+    pub text: String,
+    pub created_at_utc: DateTime<Utc>,
+}
+
+impl DirectMessageResponse {
+    pub fn new(
+        id: Uuid,
+        from: UserResponse,
+        to: UserResponse,
+        text: &str,
+        created_at_utc: DateTime<Utc>,
+    ) -> Self {
+        DirectMessageResponse {
+            id,
+            from,
+            to,
+            text: String::from(text),
+            created_at_utc,
+        }
+    }
+}
+
+impl From<&DirectMessage> for DirectMessageResponse {
+    fn from(message: &DirectMessage) -> Self {
+        DirectMessageResponse::new(
+            message.id,
+            UserResponse::from(&message.from),
+            UserResponse::from(&message.to),
+            &message.text,
+            message.created_at_utc,
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectMessagesResponse {
+    pub user_id: Uuid,
+    pub messages: Vec<DirectMessageResponse>,
+}
+
+impl DirectMessagesResponse {
+    pub fn new(user_id: Uuid, messages: Vec<DirectMessageResponse>) -> Self {
+        DirectMessagesResponse { user_id, messages }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryResponse {
+    pub room_id: Uuid,
+    pub messages: Vec<MessageResponse>,
+    pub has_more: bool,
+}
+
+impl HistoryResponse {
+    pub fn new(room_id: Uuid, messages: Vec<MessageResponse>, has_more: bool) -> Self {
+        HistoryResponse {
+            room_id,
+            messages,
+            has_more,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ThreadResponse {
+    pub root: MessageResponse,
+    pub replies: Vec<MessageResponse>,
+}
+
+impl ThreadResponse {
+    pub fn new(root: MessageResponse, replies: Vec<MessageResponse>) -> Self {
+        ThreadResponse { root, replies }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MentionsResponse {
+    pub messages: Vec<MessageResponse>,
+}
+
+impl MentionsResponse {
+    pub fn new(messages: Vec<MessageResponse>) -> Self {
+        MentionsResponse { messages }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchResultsResponse {
+    pub query: String,
+    /// Newest first.
+    pub messages: Vec<MessageResponse>,
+}
+
+impl SearchResultsResponse {
+    pub fn new(query: &str, messages: Vec<MessageResponse>) -> Self {
+        SearchResultsResponse {
+            query: String::from(query),
+            messages,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnnouncementResponse {
+    pub text: String,
+    pub created_at_utc: DateTime<Utc>,
+}
+
+impl AnnouncementResponse {
+    pub fn new(text: &str, created_at_utc: DateTime<Utc>) -> Self {
+        AnnouncementResponse {
+            text: String::from(text),
+            created_at_utc,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserMutedResponse {
+    /// Mutes hold for the name, through reconnects and other sessions.
+    pub name: String,
+    /// The user muted by id; `None` when muted by name.
+    pub user_id: Option<Uuid>,
+    /// When the mute ends; `None` once it is lifted.
+    pub until_utc: Option<DateTime<Utc>>,
+}
+
+impl UserMutedResponse {
+    pub fn new(name: &str, user_id: Option<Uuid>, until_utc: Option<DateTime<Utc>>) -> Self {
+        UserMutedResponse {
+            name: String::from(name),
+            user_id,
+            until_utc,
+        }
+    }
+}
+
+/// The protocol version and capabilities the server will use with the
+/// client from now on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WelcomeResponse {
+    pub protocol_version: u32,
+    pub server_version: String,
+    pub capabilities: Vec<String>,
+}
+
+impl WelcomeResponse {
+    pub fn new(protocol_version: u32, server_version: &str, capabilities: Vec<String>) -> Self {
+        WelcomeResponse {
+            protocol_version,
+            server_version: String::from(server_version),
+            capabilities,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserBannedResponse {
+    pub ban: Ban,
+}
+
+impl UserBannedResponse {
+    pub fn new(ban: Ban) -> Self {
+        UserBannedResponse { ban }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ErrorType {
     NameExisted,
     InvalidName,
-    InvalidRequest,
+    /// The request could not be read or makes no sense. `line` and `column`
+    /// point into the frame when the JSON is at fault, and are 0 otherwise.
+    InvalidRequest {
+        message: String,
+        line: usize,
+        column: usize,
+    },
     NotJoined,
     InvalidMessage,
+    InvalidRoomName,
+    RoomExisted,
+    RoomNotFound,
+    NotInRoom,
+    UserNotFound,
+    StorageFailed,
+    MessageNotFound,
+    NotAuthor,
+    NameMismatch,
+    AlreadyJoined,
+    InvalidPassword,
+    InvalidCredentials,
+    InvalidResumeToken,
+    InvalidReaction,
+    InvalidQuery,
+    NotModerator,
+    Muted,
+    Banned,
+    UnsupportedVersion,
+    /// Too many requests of this kind; try again after `retryAfterMs`.
+    #[serde(rename_all = "camelCase")]
+    RateLimited { retry_after_ms: u64 },
+}
+
+impl ErrorType {
+    pub fn invalid_request(message: &str) -> Self {
+        ErrorType::InvalidRequest {
+            message: String::from(message),
+            line: 0,
+            column: 0,
+        }
+    }
+}
+
+impl From<&serde_json::Error> for ErrorType {
+    fn from(err: &serde_json::Error) -> Self {
+        ErrorType::InvalidRequest {
+            message: err.to_string(),
+            line: err.line(),
+            column: err.column(),
+        }
+    }
 }
\ No newline at end of file
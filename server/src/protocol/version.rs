@@ -0,0 +1,139 @@
+use serde_json::Value;
+use warp::ws::Message;
+
+use super::{
+    encoding::Encoding,
+    request::HelloRequestData,
+    response::{ErrorType, ResponseData, ResponseMessage, WelcomeResponse},
+};
+use crate::error::Result;
+
+/// The version this server speaks, and the oldest one it still writes.
+/// Version 2 added details to `InvalidRequest` errors, a bare name in 1.
+/// Errors that came after it have no older form and go out as they are.
+pub const PROTOCOL_VERSION: u32 = 2;
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Echoes `requestId` on the responses to tagged requests. Clients that
+/// predate `Hello` get their ids back too, as they always have, so this
+/// only tells new clients they can count on it.
+pub const REQUEST_IDS: &str = "requestIds";
+pub const CAPABILITIES: [&str; 1] = [REQUEST_IDS];
+
+/// What was agreed with a client in its `Hello`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Protocol {
+    pub version: u32,
+    pub capabilities: Vec<String>,
+}
+
+impl Protocol {
+    /// Spoken with clients that never say `Hello`, which predate it.
+    pub fn legacy() -> Self {
+        Protocol {
+            version: MIN_PROTOCOL_VERSION,
+            capabilities: Vec::new(),
+        }
+    }
+
+    /// The newest version both sides speak, with the capabilities both know.
+    pub fn negotiate(hello: &HelloRequestData) -> std::result::Result<Self, ErrorType> {
+        if hello.protocol_version < MIN_PROTOCOL_VERSION {
+            return Err(ErrorType::UnsupportedVersion);
+        }
+        let capabilities: Vec<String> = CAPABILITIES
+            .iter()
+            .filter(|capability| hello.capabilities.iter().any(|c| c == *capability))
+            .map(|capability| String::from(*capability))
+            .collect();
+        Ok(Protocol {
+            version: hello.protocol_version.min(PROTOCOL_VERSION),
+            capabilities,
+        })
+    }
+
+    pub fn has(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+
+    pub fn welcome(&self) -> WelcomeResponse {
+        WelcomeResponse::new(
+            self.version,
+            env!("CARGO_PKG_VERSION"),
+            self.capabilities.clone(),
+        )
+    }
+
+    /// Writes `response_message` the way this version of the protocol reads.
+    pub fn encode(&self, response_message: ResponseMessage, encoding: Encoding) -> Result<Message> {
+        let is_invalid_request = matches!(
+            response_message.response_data,
+            ResponseData::Error(ErrorType::InvalidRequest { .. })
+        );
+        if self.version >= 2 || !is_invalid_request {
+            return encoding.encode(&response_message);
+        }
+
+        // `{"InvalidRequest": {...}}` goes back to `"InvalidRequest"`
+        let mut value = serde_json::to_value(&response_message)?;
+        value["payload"] = Value::from("InvalidRequest");
+        encoding.encode(&value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello(protocol_version: u32, capabilities: &[&str]) -> HelloRequestData {
+        HelloRequestData {
+            protocol_version,
+            capabilities: capabilities.iter().map(|c| String::from(*c)).collect(),
+        }
+    }
+
+    fn encode(protocol: &Protocol, response_message: ResponseMessage) -> Value {
+        let frame = protocol.encode(response_message, Encoding::Json).unwrap();
+        serde_json::from_str(frame.to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn older_versions_get_bare_error_names() {
+        let response_message = ResponseMessage::new(
+            ResponseData::Error(ErrorType::invalid_request("expected a text frame")),
+            Some(String::from("7")),
+        );
+
+        let legacy = encode(&Protocol::legacy(), response_message.clone());
+        assert_eq!(
+            legacy,
+            serde_json::json!({"type": "Error", "payload": "InvalidRequest", "requestId": "7"})
+        );
+
+        let protocol = Protocol::negotiate(&hello(3, &["requestIds", "telepathy"])).unwrap();
+        assert_eq!(protocol.version, PROTOCOL_VERSION);
+        assert_eq!(protocol.capabilities, vec![REQUEST_IDS]);
+        let current = encode(&protocol, response_message);
+        assert_eq!(current["requestId"], "7");
+        assert_eq!(current["payload"]["InvalidRequest"]["line"], 0);
+
+        assert_eq!(Protocol::negotiate(&hello(1, &[])), Ok(Protocol::legacy()));
+        assert_eq!(
+            Protocol::negotiate(&hello(0, &[])),
+            Err(ErrorType::UnsupportedVersion)
+        );
+    }
+
+    #[test]
+    fn older_versions_keep_details_of_newer_errors() {
+        let response_message = ResponseMessage::new(
+            ResponseData::Error(ErrorType::RateLimited {
+                retry_after_ms: 1500,
+            }),
+            None,
+        );
+
+        let legacy = encode(&Protocol::legacy(), response_message);
+        assert_eq!(legacy["payload"]["RateLimited"]["retryAfterMs"], 1500);
+    }
+}
@@ -0,0 +1,263 @@
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    net::IpAddr,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use uuid::Uuid;
+
+/// How long rejected requests keep counting towards a disconnection.
+pub const STRIKE_WINDOW: Duration = Duration::from_secs(60);
+
+/// At most `requests` in a burst, refilled evenly over `per`. Written as
+/// `requests/seconds`, e.g. `5/10`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct RateLimit {
+    pub requests: u32,
+    pub per: Duration,
+}
+
+impl RateLimit {
+    pub fn new(requests: u32, per: Duration) -> Self {
+        RateLimit { requests, per }
+    }
+
+    fn tokens_per_sec(&self) -> f64 {
+        f64::from(self.requests) / self.per.as_secs_f64()
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (requests, secs) = value
+            .split_once('/')
+            .ok_or_else(|| String::from("expected requests/seconds"))?;
+        let requests: u32 = requests.trim().parse().map_err(|err| format!("{}", err))?;
+        let secs: u64 = secs.trim().parse().map_err(|err| format!("{}", err))?;
+        if requests == 0 || secs == 0 {
+            return Err(String::from("requests and seconds must be at least 1"));
+        }
+        Ok(RateLimit::new(requests, Duration::from_secs(secs)))
+    }
+}
+
+impl TryFrom<String> for RateLimit {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// Whose requests a bucket counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Subject {
+    Client(Uuid),
+    Address(IpAddr),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+    limit: RateLimit,
+}
+
+impl TokenBucket {
+    fn full(limit: RateLimit, now: Instant) -> Self {
+        TokenBucket {
+            tokens: f64::from(limit.requests),
+            updated: now,
+            limit,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.tokens_per_sec())
+            .min(f64::from(self.limit.requests));
+        self.updated = now;
+    }
+
+    fn is_full(&self) -> bool {
+        self.tokens >= f64::from(self.limit.requests)
+    }
+
+    /// How long until a whole token is available.
+    fn wait(&self) -> Duration {
+        Duration::from_secs_f64((1.0 - self.tokens).max(0.0) / self.limit.tokens_per_sec())
+    }
+}
+
+/// A request turned away, and whose bucket turned it away. When both the
+/// client's and its address's are empty, it is the client's.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limited {
+    pub retry_after: Duration,
+    pub subject: Subject,
+}
+
+/// Token buckets per subject and request kind, and the strikes of the
+/// clients that went over them.
+#[derive(Default)]
+pub struct RateLimiter {
+    limits: HashMap<String, RateLimit>,
+    /// Shared by every client behind an address, so larger.
+    address_limits: HashMap<String, RateLimit>,
+    buckets: HashMap<(Subject, &'static str), TokenBucket>,
+    strikes: HashMap<Uuid, (u32, Instant)>,
+}
+
+impl RateLimiter {
+    pub fn new(
+        limits: HashMap<String, RateLimit>,
+        address_limits: HashMap<String, RateLimit>,
+    ) -> Self {
+        RateLimiter {
+            limits,
+            address_limits,
+            ..RateLimiter::default()
+        }
+    }
+
+    /// Takes a token for `kind` from the bucket of every one of `subjects`,
+    /// or none of them and returns how long to wait if any bucket is empty.
+    /// Kinds without a limit for a subject always pass for it.
+    pub fn check(
+        &mut self,
+        kind: &'static str,
+        subjects: &[Subject],
+        now: Instant,
+    ) -> Result<(), Limited> {
+        let mut limited: Option<Limited> = None;
+        for subject in subjects {
+            let limits = match subject {
+                Subject::Client(_) => &self.limits,
+                Subject::Address(_) => &self.address_limits,
+            };
+            let limit = match limits.get(kind) {
+                Some(limit) => *limit,
+                None => continue,
+            };
+            let bucket = self
+                .buckets
+                .entry((*subject, kind))
+                .or_insert_with(|| TokenBucket::full(limit, now));
+            bucket.refill(now);
+            let wait = bucket.wait();
+            if wait == Duration::from_secs(0) {
+                continue;
+            }
+            limited = Some(match limited {
+                Some(Limited {
+                    retry_after,
+                    subject: Subject::Client(client_id),
+                }) => Limited {
+                    retry_after: retry_after.max(wait),
+                    subject: Subject::Client(client_id),
+                },
+                Some(Limited { retry_after, .. }) => Limited {
+                    retry_after: retry_after.max(wait),
+                    subject: *subject,
+                },
+                None => Limited {
+                    retry_after: wait,
+                    subject: *subject,
+                },
+            });
+        }
+        if let Some(limited) = limited {
+            return Err(limited);
+        }
+        for subject in subjects {
+            if let Some(bucket) = self.buckets.get_mut(&(*subject, kind)) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts a rejected request of `client_id` and returns its strikes
+    /// within the last `STRIKE_WINDOW`.
+    pub fn strike(&mut self, client_id: Uuid, now: Instant) -> u32 {
+        let strikes = self.strikes.entry(client_id).or_insert((0, now));
+        if now.saturating_duration_since(strikes.1) > STRIKE_WINDOW {
+            strikes.0 = 0;
+        }
+        *strikes = (strikes.0 + 1, now);
+        strikes.0
+    }
+
+    pub fn forget(&mut self, client_id: &Uuid) {
+        self.buckets
+            .retain(|(subject, _), _| *subject != Subject::Client(*client_id));
+        self.strikes.remove(client_id);
+    }
+
+    /// Drops the buckets that have filled up again, which would behave the
+    /// same if created afresh.
+    pub fn prune(&mut self, now: Instant) {
+        self.buckets.retain(|_, bucket| {
+            bucket.refill(now);
+            !bucket.is_full()
+        });
+        self.strikes
+            .retain(|_, (_, last)| now.saturating_duration_since(*last) <= STRIKE_WINDOW);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_refill_evenly_and_are_shared_by_address() {
+        let limits = |limit: &str| {
+            vec![(String::from("postMessage"), limit.parse().unwrap())]
+                .into_iter()
+                .collect()
+        };
+        let mut limiter = RateLimiter::new(limits("2/10"), limits("3/10"));
+        let address = Subject::Address("203.0.113.7".parse().unwrap());
+        let (laptop_id, phone_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let laptop = [Subject::Client(laptop_id), address];
+        let phone = [Subject::Client(phone_id), address];
+        let now = Instant::now();
+
+        assert!(limiter.check("postMessage", &laptop, now).is_ok());
+        assert!(limiter.check("postMessage", &laptop, now).is_ok());
+        assert!(limiter.check("postMessage", &phone, now).is_ok());
+        assert_eq!(
+            limiter.check("postMessage", &laptop, now),
+            Err(Limited {
+                retry_after: Duration::from_secs(5),
+                subject: Subject::Client(laptop_id),
+            })
+        );
+        // The phone has tokens left, but not their address
+        assert_eq!(
+            limiter.check("postMessage", &phone, now),
+            Err(Limited {
+                retry_after: Duration::from_secs_f64(10.0 / 3.0),
+                subject: address,
+            })
+        );
+        assert!(limiter.check("join", &laptop, now).is_ok());
+
+        let later = now + Duration::from_secs(6);
+        assert!(limiter.check("postMessage", &phone, later).is_ok());
+        assert!(limiter.check("postMessage", &laptop, later).is_err());
+
+        limiter.prune(now + Duration::from_secs(60));
+        assert!(limiter.buckets.is_empty());
+
+        assert!("0/10".parse::<RateLimit>().is_err());
+        assert!("5".parse::<RateLimit>().is_err());
+    }
+}
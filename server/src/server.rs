@@ -1,60 +1,268 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    convert::Infallible,
+    fmt::Display,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
 
-use futures::{StreamExt, TryStreamExt};
-use log::{error, info};
+use futures::{future, Sink, SinkExt, StreamExt, TryStreamExt};
+use hyper::{server::conn::Http, service::Service};
+use log::{debug, error, info};
+use tokio::net::TcpListener;
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::UnboundedSender;
-use tokio_stream::wrappers::{UnboundedReceiverStream};
-use warp::{ws::WebSocket, Filter};
+use warp::{
+    http::HeaderValue,
+    ws::{Message, WebSocket},
+    Filter, Rejection, Reply,
+};
 
-use crate::{client::Client, protocol::request::RequestMessage, worker::Worker};
+use crate::{
+    admin,
+    auth::{self, Identity, TokenVerifier},
+    client::{Client, Inbound},
+    config::ServerConfig,
+    error::{Error, Result},
+    outbox::Inbox,
+    protocol::{encoding::Encoding, request::RequestMessage},
+    store::{memory::MemoryFeedStore, FeedStore},
+    tls::ReloadableCertificate,
+    worker::Worker,
+};
 
-const MAX_FRAME_SIZE: usize = 1 << 16;
+/// Peer of a TLS connection, which warp cannot see past the acceptor.
+#[derive(Clone, Copy)]
+struct PeerAddress(SocketAddr);
 
 pub struct Server {
-    port: u16,
+    config: ServerConfig,
     worker: Arc<Worker>,
+    verifier: Option<Arc<dyn TokenVerifier>>,
+    certificate: Option<Arc<ReloadableCertificate>>,
 }
 
 impl Server {
     pub fn new(port: u16) -> Self {
-        Server {
+        let config = ServerConfig {
             port,
-            worker: Arc::new(Worker::new(Some(Duration::from_secs(5)))),
-        }
+            ..ServerConfig::default()
+        };
+        Self::with_config(config, Box::new(MemoryFeedStore::default()))
+            .expect("in-memory feed store cannot fail")
+    }
+
+    pub fn with_config(config: ServerConfig, store: Box<dyn FeedStore>) -> Result<Self> {
+        let worker = Worker::with_config(&config, store)?;
+        let certificate = match (&config.tls_cert_path, &config.tls_key_path) {
+            (Some(cert_path), Some(key_path)) => {
+                Some(Arc::new(ReloadableCertificate::load(cert_path, key_path)?))
+            }
+            _ => None,
+        };
+        Ok(Server {
+            config,
+            worker: Arc::new(worker),
+            verifier: None,
+            certificate,
+        })
+    }
+
+    /// Requires a valid token before upgrading connections on `/feed`.
+    pub fn with_verifier(mut self, verifier: Arc<dyn TokenVerifier>) -> Self {
+        self.verifier = Some(verifier);
+        self
     }
 
     pub async fn run(&self) {
-        println!("{:?}", MAX_FRAME_SIZE);
+        let max_frame_size = self.config.max_frame_size;
         let (sender, receiver) = mpsc::unbounded_channel::<RequestMessage>();
         let worker = self.worker.clone();
 
         let feed = warp::path("feed")
             .and(warp::ws())
+            .and(warp::header::optional::<String>("sec-websocket-protocol"))
+            .and(Self::admit(self.worker.clone()))
+            .and(auth::authenticate(self.verifier.clone()))
             .and(warp::any().map(move || sender.clone()))
             .and(warp::any().map(move || worker.clone()))
             .map(
-                |ws: warp::ws::Ws, sender: UnboundedSender<RequestMessage>, worker: Arc<Worker>| {
-                    ws.max_frame_size(MAX_FRAME_SIZE)
+                move |ws: warp::ws::Ws,
+                      offer: Option<String>,
+                      address: Option<IpAddr>,
+                      identity: Option<Identity>,
+                      sender: UnboundedSender<RequestMessage>,
+                      worker: Arc<Worker>| {
+                    // Clients that offer no known subprotocol are spoken to in JSON
+                    let encoding = offer.as_deref().and_then(Encoding::from_offer);
+                    let mut reply = ws
+                        .max_frame_size(max_frame_size)
                         .on_upgrade(move |web_socket| async move {
-                            tokio::spawn(Self::process_client(worker, web_socket, sender));
+                            let client = Client::with_identity(identity)
+                                .with_address(address)
+                                .with_encoding(encoding.unwrap_or_default());
+                            tokio::spawn(Self::process_client(worker, web_socket, sender, client));
                         })
+                        .into_response();
+                    if let Some(encoding) = encoding {
+                        reply.headers_mut().insert(
+                            "sec-websocket-protocol",
+                            HeaderValue::from_static(encoding.subprotocol()),
+                        );
+                    }
+                    reply
                 },
             );
+        let routes = feed
+            .or(admin::routes(
+                self.worker.clone(),
+                self.config.admin_token.clone(),
+            ))
+            .recover(auth::recover);
 
         let shutdown = async {
             tokio::signal::ctrl_c()
                 .await
                 .expect("failed to install CTRL+C signal handler");
         };
-        let (_, serving) =
-            warp::serve(feed).bind_with_graceful_shutdown(([127, 0, 0, 1], self.port), shutdown);
-
+        let address = SocketAddr::new(self.config.bind_address, self.config.port);
         let running_hub = self.worker.run(receiver);
 
+        let certificate = match &self.certificate {
+            Some(certificate) => certificate.clone(),
+            None => {
+                let (address, serving) =
+                    warp::serve(routes).bind_with_graceful_shutdown(address, shutdown);
+                info!("Listening on {}", address);
+                tokio::select! {
+                    _ = serving => {},
+                    _ = running_hub => {},
+                }
+                return;
+            }
+        };
+
+        let listener = match TcpListener::bind(address).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!("Failed to bind {}: {}", address, err);
+                return;
+            }
+        };
+        info!("Listening on {} with TLS", address);
+        tokio::spawn(Self::reload_on_hangup(certificate.clone()));
+
+        let acceptor = certificate.acceptor();
+        let serving = async {
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        error!("Failed to accept connection: {}", err);
+                        continue;
+                    }
+                };
+                let acceptor = acceptor.clone();
+                let mut routes = warp::service(routes.clone());
+                let service = hyper::service::service_fn(move |mut request| {
+                    request.extensions_mut().insert(PeerAddress(peer));
+                    routes.call(request)
+                });
+                tokio::spawn(async move {
+                    let stream = match acceptor.accept(stream).await {
+                        Ok(stream) => stream,
+                        Err(err) => {
+                            debug!("TLS handshake with {} failed: {}", peer, err);
+                            return;
+                        }
+                    };
+                    let serving = Http::new()
+                        .http1_only(true)
+                        .serve_connection(stream, service)
+                        .with_upgrades();
+                    if let Err(err) = serving.await {
+                        debug!("Connection from {} failed: {}", peer, err);
+                    }
+                });
+            }
+        };
+
         tokio::select! {
             _ = serving => {},
             _ = running_hub => {},
+            _ = shutdown => {},
+        }
+    }
+
+    /// Refuses connections from banned addresses before they are upgraded,
+    /// and extracts the address of the others.
+    fn admit(
+        worker: Arc<Worker>,
+    ) -> impl Filter<Extract = (Option<IpAddr>,), Error = Rejection> + Clone {
+        Self::remote_address().and_then(move |address: Option<IpAddr>| {
+            let worker = worker.clone();
+            async move {
+                match address {
+                    Some(address) if worker.is_banned(&address).await => {
+                        Err(warp::reject::custom(auth::Banned))
+                    }
+                    _ => Ok(address),
+                }
+            }
+        })
+    }
+
+    fn remote_address() -> impl Filter<Extract = (Option<IpAddr>,), Error = Infallible> + Clone {
+        warp::addr::remote()
+            .and(warp::ext::optional::<PeerAddress>())
+            .map(|remote: Option<SocketAddr>, peer: Option<PeerAddress>| {
+                remote
+                    .or_else(|| peer.map(|PeerAddress(peer)| peer))
+                    .map(|address| address.ip())
+            })
+    }
+
+    #[cfg(unix)]
+    async fn reload_on_hangup(certificate: Arc<ReloadableCertificate>) {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(hangups) => hangups,
+            Err(err) => {
+                error!("Failed to install SIGHUP handler: {}", err);
+                return;
+            }
+        };
+        while hangups.recv().await.is_some() {
+            match certificate.reload() {
+                Ok(()) => info!("Reloaded TLS certificate"),
+                Err(err) => error!("Failed to reload TLS certificate: {}", err),
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    async fn reload_on_hangup(_certificate: Arc<ReloadableCertificate>) {}
+
+    /// Writes the responses queued for `client` to `sink`, taking each out of
+    /// the outbox only once the sink is ready for it. Responses to a stalled
+    /// socket stay queued, where the overflow policy deals with them. Once
+    /// the client falls behind the sink is abandoned rather than waited on,
+    /// as a stalled socket would never take the close frame.
+    async fn write_client<S>(client: &Client, inbox: Inbox, sink: S) -> Result<()>
+    where
+        S: Sink<Message>,
+        S::Error: Display,
+    {
+        let fell_behind = inbox.fell_behind();
+        let forwarding = client
+            .write(inbox.into_stream())
+            .forward(sink.sink_map_err(|err| Error::System(err.to_string())));
+        tokio::select! {
+            result = forwarding => result,
+            _ = fell_behind => {
+                info!("Dropping socket of client {} for falling behind", client.id);
+                Ok(())
+            }
         }
     }
 
@@ -62,28 +270,33 @@ impl Server {
         hub: Arc<Worker>,
         web_socket: WebSocket,
         input_sender: UnboundedSender<RequestMessage>,
+        client: Client,
     ) {
-        let output_receiver = hub.subscribe();
         let (ws_sink, ws_stream) = web_socket.split();
-        let client = Client::new();
 
+        let output_receiver = hub.on_connect(&client).await;
         info!("Client {} connected", client.id);
 
-        let reading = client.read(ws_stream).try_for_each(|input_parcel| async {
-            input_sender.send(input_parcel).unwrap();
-            Ok(())
+        let mut invalid_frames = 0;
+        let reading = client.read(ws_stream).try_for_each(|inbound| {
+            match inbound {
+                Inbound::Request(input_parcel) => {
+                    invalid_frames = 0;
+                    input_sender.send(input_parcel).unwrap();
+                }
+                Inbound::Welcome(welcome) => {
+                    invalid_frames = 0;
+                    hub.welcome(client.id, welcome);
+                }
+                Inbound::Invalid(error_type) => {
+                    invalid_frames += 1;
+                    hub.reject_frame(client.id, error_type, invalid_frames);
+                }
+            }
+            future::ok(())
         });
 
-        let (tx, rx) = mpsc::unbounded_channel();
-        let stream = UnboundedReceiverStream::new(rx);
-        let receiver_stream = tokio_stream::wrappers::BroadcastStream::new(output_receiver);
-        tokio::spawn(stream.forward(ws_sink));
-        let writing = client
-            .write(receiver_stream.into_stream())
-            .try_for_each(|message| async {
-                tx.send(Ok(message)).unwrap();
-                Ok(())
-            });
+        let writing = Self::write_client(&client, output_receiver, ws_sink);
 
         if let Err(err) = tokio::select! {
             result = reading => result,
@@ -96,3 +309,56 @@ impl Server {
         info!("Client {} disconnected", client.id);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::channel::mpsc;
+    use tokio::{runtime::Runtime, time};
+
+    use super::*;
+    use crate::{
+        outbox::{Delivery, OverflowPolicy},
+        protocol::response::{ErrorType, ResponseData},
+    };
+
+    #[test]
+    fn stalled_sockets_are_dropped_once_they_fall_behind() {
+        let config = ServerConfig {
+            outbox_capacity: 2,
+            overflow_policy: OverflowPolicy::Disconnect,
+            ..ServerConfig::default()
+        };
+        let worker = Worker::with_config(&config, Box::new(MemoryFeedStore::default())).unwrap();
+        let client = Client::new();
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let inbox = worker.on_connect(&client).await;
+            // Never read, so the sink takes a single frame and then stalls
+            let (sink, _unread) = mpsc::channel::<Message>(0);
+            let writing = Server::write_client(&client, inbox, sink);
+            let flooding = async {
+                let mut deliveries = Vec::new();
+                for _ in 0..8 {
+                    let delivery = worker.outboxes.read().unwrap()[&client.id]
+                        .send(ResponseData::Error(ErrorType::NotJoined));
+                    deliveries.push(delivery);
+                    tokio::task::yield_now().await;
+                }
+                deliveries
+            };
+            let (written, deliveries) =
+                tokio::join!(time::timeout(Duration::from_secs(5), writing), flooding);
+            assert!(deliveries.contains(&Delivery::Disconnected));
+            assert_eq!(deliveries.last(), Some(&Delivery::Closed));
+            assert!(written
+                .expect("Expected the stalled socket to be dropped")
+                .is_ok());
+
+            worker.on_disconnect(client.id).await;
+            assert!(!worker.outboxes.read().unwrap().contains_key(&client.id));
+        });
+    }
+}
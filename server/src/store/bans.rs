@@ -0,0 +1,107 @@
+use std::{
+    collections::HashSet,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+use super::replay_lines;
+use crate::{error::Result, model::ban::Ban};
+
+/// Bans in force, appended to a JSON-lines file when opened from one so
+/// that they survive restarts.
+#[derive(Default)]
+pub struct BanList {
+    file: Option<File>,
+    bans: HashSet<Ban>,
+}
+
+impl BanList {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut bans = HashSet::new();
+        replay_lines(&path, |ban| {
+            bans.insert(ban);
+            Ok(())
+        })?;
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(BanList {
+            file: Some(file),
+            bans,
+        })
+    }
+
+    /// Returns false if `ban` was already in force.
+    pub fn add(&mut self, ban: Ban) -> Result<bool> {
+        if self.bans.contains(&ban) {
+            return Ok(false);
+        }
+        if let Some(file) = &mut self.file {
+            let mut line = serde_json::to_string(&ban)?;
+            line.push('\n');
+            file.write_all(line.as_bytes())?;
+            file.flush()?;
+        }
+        self.bans.insert(ban);
+        Ok(true)
+    }
+
+    pub fn contains(&self, ban: &Ban) -> bool {
+        self.bans.contains(ban)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, net::IpAddr};
+
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn reopening_keeps_bans() {
+        let path = env::temp_dir().join(format!("bans-{}.jsonl", Uuid::new_v4()));
+        let address: IpAddr = "203.0.113.7".parse().unwrap();
+
+        {
+            let mut bans = BanList::open(&path).unwrap();
+            assert!(bans.add(Ban::Name(String::from("spammer"))).unwrap());
+            assert!(bans.add(Ban::Address(address)).unwrap());
+            assert!(!bans.add(Ban::Address(address)).unwrap());
+        }
+
+        let bans = BanList::open(&path).unwrap();
+        assert!(bans.contains(&Ban::Name(String::from("spammer"))));
+        assert!(bans.contains(&Ban::Address(address)));
+        assert!(!bans.contains(&Ban::Name(String::from("daolavi"))));
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reopening_drops_a_torn_last_line() {
+        let path = env::temp_dir().join(format!("bans-{}.jsonl", Uuid::new_v4()));
+
+        {
+            let mut bans = BanList::open(&path).unwrap();
+            assert!(bans.add(Ban::Name(String::from("spammer"))).unwrap());
+        }
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"type":"address","val"#).unwrap();
+
+        {
+            let mut bans = BanList::open(&path).unwrap();
+            assert!(bans.contains(&Ban::Name(String::from("spammer"))));
+            assert!(bans.add(Ban::Name(String::from("flooder"))).unwrap());
+        }
+
+        let bans = BanList::open(&path).unwrap();
+        assert!(bans.contains(&Ban::Name(String::from("spammer"))));
+        assert!(bans.contains(&Ban::Name(String::from("flooder"))));
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,237 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{memory::MemoryFeedStore, replay_lines, FeedStore, StoredRoom};
+use crate::{
+    error::Result,
+    model::{account::Account, feed::Feed, message::Message},
+};
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload", rename_all = "camelCase")]
+enum Record {
+    RoomAdded(StoredRoom),
+    MessageAdded(Message),
+    #[serde(rename_all = "camelCase")]
+    MessageEdited {
+        id: Uuid,
+        text: String,
+        edited_at_utc: DateTime<Utc>,
+    },
+    MessageDeleted {
+        id: Uuid,
+    },
+    #[serde(rename_all = "camelCase")]
+    ReactionAdded {
+        id: Uuid,
+        emoji: String,
+        user_id: Uuid,
+    },
+    #[serde(rename_all = "camelCase")]
+    ReactionRemoved {
+        id: Uuid,
+        emoji: String,
+        user_id: Uuid,
+    },
+    AccountAdded(Account),
+}
+
+/// Append-only JSON-lines log, replayed into memory when opened.
+pub struct JsonLinesFeedStore {
+    file: File,
+    memory: MemoryFeedStore,
+}
+
+impl JsonLinesFeedStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut memory = MemoryFeedStore::default();
+        replay_lines(&path, |record| match record {
+            Record::RoomAdded(room) => memory.add_room(room),
+            Record::MessageAdded(message) => memory.add_message(message),
+            Record::MessageEdited {
+                id,
+                text,
+                edited_at_utc,
+            } => memory.edit_message(&id, &text, edited_at_utc),
+            Record::MessageDeleted { id } => memory.delete_message(&id),
+            Record::ReactionAdded { id, emoji, user_id } => memory.react(&id, &emoji, user_id),
+            Record::ReactionRemoved { id, emoji, user_id } => memory.unreact(&id, &emoji, user_id),
+            Record::AccountAdded(account) => memory.add_account(account),
+        })?;
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(JsonLinesFeedStore { file, memory })
+    }
+
+    fn append(&mut self, record: &Record) -> Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+impl FeedStore for JsonLinesFeedStore {
+    fn add_room(&mut self, room: StoredRoom) -> Result<()> {
+        self.append(&Record::RoomAdded(room.clone()))?;
+        self.memory.add_room(room)
+    }
+
+    fn rooms(&self) -> Vec<StoredRoom> {
+        self.memory.rooms()
+    }
+
+    fn add_message(&mut self, message: Message) -> Result<()> {
+        self.append(&Record::MessageAdded(message.clone()))?;
+        self.memory.add_message(message)
+    }
+
+    fn edit_message(&mut self, id: &Uuid, text: &str, edited_at_utc: DateTime<Utc>) -> Result<()> {
+        self.append(&Record::MessageEdited {
+            id: *id,
+            text: String::from(text),
+            edited_at_utc,
+        })?;
+        self.memory.edit_message(id, text, edited_at_utc)
+    }
+
+    fn delete_message(&mut self, id: &Uuid) -> Result<()> {
+        self.append(&Record::MessageDeleted { id: *id })?;
+        self.memory.delete_message(id)
+    }
+
+    fn react(&mut self, id: &Uuid, emoji: &str, user_id: Uuid) -> Result<()> {
+        self.append(&Record::ReactionAdded {
+            id: *id,
+            emoji: String::from(emoji),
+            user_id,
+        })?;
+        self.memory.react(id, emoji, user_id)
+    }
+
+    fn unreact(&mut self, id: &Uuid, emoji: &str, user_id: Uuid) -> Result<()> {
+        self.append(&Record::ReactionRemoved {
+            id: *id,
+            emoji: String::from(emoji),
+            user_id,
+        })?;
+        self.memory.unreact(id, emoji, user_id)
+    }
+
+    fn message(&self, id: &Uuid) -> Option<&Message> {
+        self.memory.message(id)
+    }
+
+    fn feed(&self, room_id: &Uuid) -> Option<&Feed> {
+        self.memory.feed(room_id)
+    }
+
+    fn mentions(&self, user_id: &Uuid, limit: usize) -> Vec<&Message> {
+        self.memory.mentions(user_id, limit)
+    }
+
+    fn add_account(&mut self, account: Account) -> Result<()> {
+        self.append(&Record::AccountAdded(account.clone()))?;
+        self.memory.add_account(account)
+    }
+
+    fn account(&self, name: &str) -> Option<&Account> {
+        self.memory.account(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::model::user::User;
+
+    #[test]
+    fn reopening_replays_rooms_messages_and_reactions() {
+        let path = env::temp_dir().join(format!("feed-{}.jsonl", Uuid::new_v4()));
+        let room = StoredRoom::new(Uuid::new_v4(), "general");
+        let user = User::new(Uuid::new_v4(), "daolavi");
+
+        {
+            let mut store = JsonLinesFeedStore::open(&path).unwrap();
+            store.add_room(room.clone()).unwrap();
+            let message = Message::new(Uuid::new_v4(), room.id, user.clone(), "Hello", Utc::now());
+            let message_id = message.id;
+            store.add_message(message).unwrap();
+            store.react(&message_id, "👍", user.id).unwrap();
+            store.react(&message_id, "🎉", user.id).unwrap();
+            store.unreact(&message_id, "🎉", user.id).unwrap();
+        }
+
+        let store = JsonLinesFeedStore::open(&path).unwrap();
+        assert_eq!(store.rooms(), vec![room.clone()]);
+        let texts: Vec<&str> = store
+            .feed(&room.id)
+            .unwrap()
+            .iter()
+            .map(|message| message.text.as_str())
+            .collect();
+        assert_eq!(texts, vec!["Hello"]);
+        let message = store.feed(&room.id).unwrap().latest(1)[0].clone();
+        let reactions: Vec<&str> = message.reactions.keys().map(String::as_str).collect();
+        assert_eq!(reactions, vec!["👍"]);
+        assert!(message.has_reacted("👍", &user.id));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reopening_drops_a_torn_last_line() {
+        let path = env::temp_dir().join(format!("feed-{}.jsonl", Uuid::new_v4()));
+        let first = StoredRoom::new(Uuid::new_v4(), "general");
+        let second = StoredRoom::new(Uuid::new_v4(), "random");
+
+        {
+            let mut store = JsonLinesFeedStore::open(&path).unwrap();
+            store.add_room(first.clone()).unwrap();
+        }
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"type":"roomAdded","payload":{"id":"#)
+            .unwrap();
+
+        {
+            let mut store = JsonLinesFeedStore::open(&path).unwrap();
+            assert_eq!(store.rooms(), vec![first.clone()]);
+            store.add_room(second.clone()).unwrap();
+        }
+
+        let store = JsonLinesFeedStore::open(&path).unwrap();
+        assert_eq!(store.rooms(), vec![first, second]);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reopening_fails_on_a_bad_line_before_the_last() {
+        let path = env::temp_dir().join(format!("feed-{}.jsonl", Uuid::new_v4()));
+
+        {
+            let mut store = JsonLinesFeedStore::open(&path).unwrap();
+            store
+                .add_room(StoredRoom::new(Uuid::new_v4(), "general"))
+                .unwrap();
+        }
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::write(&path, format!("{{\"type\"\n{}", contents)).unwrap();
+
+        assert!(JsonLinesFeedStore::open(&path).is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use super::{FeedStore, StoredRoom};
+use crate::{
+    error::Result,
+    model::{account::Account, feed::Feed, message::Message},
+};
+
+#[derive(Default)]
+pub struct MemoryFeedStore {
+    rooms: Vec<StoredRoom>,
+    feeds: HashMap<Uuid, Feed>,
+    room_by_message: HashMap<Uuid, Uuid>,
+    mentions_by_user: HashMap<Uuid, Vec<Uuid>>,
+    accounts: HashMap<String, Account>,
+}
+
+impl MemoryFeedStore {
+    fn feed_of_message_mut(&mut self, id: &Uuid) -> Option<&mut Feed> {
+        let room_id = self.room_by_message.get(id)?;
+        self.feeds.get_mut(room_id)
+    }
+
+    fn message_mut(&mut self, id: &Uuid) -> Option<&mut Message> {
+        self.feed_of_message_mut(id)?.get_mut(id)
+    }
+}
+
+impl FeedStore for MemoryFeedStore {
+    fn add_room(&mut self, room: StoredRoom) -> Result<()> {
+        self.feeds.entry(room.id).or_default();
+        self.rooms.push(room);
+        Ok(())
+    }
+
+    fn rooms(&self) -> Vec<StoredRoom> {
+        self.rooms.clone()
+    }
+
+    fn add_message(&mut self, message: Message) -> Result<()> {
+        self.room_by_message.insert(message.id, message.room_id);
+        for user_id in &message.mentions {
+            self.mentions_by_user
+                .entry(*user_id)
+                .or_default()
+                .push(message.id);
+        }
+        self.feeds
+            .entry(message.room_id)
+            .or_default()
+            .add_message(message);
+        Ok(())
+    }
+
+    fn edit_message(&mut self, id: &Uuid, text: &str, edited_at_utc: DateTime<Utc>) -> Result<()> {
+        if let Some(feed) = self.feed_of_message_mut(id) {
+            feed.edit_message(id, text, edited_at_utc);
+        }
+        Ok(())
+    }
+
+    fn delete_message(&mut self, id: &Uuid) -> Result<()> {
+        if let Some(feed) = self.feed_of_message_mut(id) {
+            feed.delete_message(id);
+        }
+        Ok(())
+    }
+
+    fn react(&mut self, id: &Uuid, emoji: &str, user_id: Uuid) -> Result<()> {
+        if let Some(message) = self.message_mut(id) {
+            message.react(emoji, user_id);
+        }
+        Ok(())
+    }
+
+    fn unreact(&mut self, id: &Uuid, emoji: &str, user_id: Uuid) -> Result<()> {
+        if let Some(message) = self.message_mut(id) {
+            message.unreact(emoji, &user_id);
+        }
+        Ok(())
+    }
+
+    fn message(&self, id: &Uuid) -> Option<&Message> {
+        let room_id = self.room_by_message.get(id)?;
+        self.feeds.get(room_id)?.get(id)
+    }
+
+    fn feed(&self, room_id: &Uuid) -> Option<&Feed> {
+        self.feeds.get(room_id)
+    }
+
+    fn mentions(&self, user_id: &Uuid, limit: usize) -> Vec<&Message> {
+        let message_ids = match self.mentions_by_user.get(user_id) {
+            Some(message_ids) => message_ids,
+            None => return Vec::new(),
+        };
+        let mut mentions: Vec<&Message> = message_ids
+            .iter()
+            .rev()
+            .filter_map(|id| self.message(id))
+            .filter(|message| !message.deleted)
+            .take(limit)
+            .collect();
+        mentions.reverse();
+        mentions
+    }
+
+    fn add_account(&mut self, account: Account) -> Result<()> {
+        self.accounts.insert(account.name.clone(), account);
+        Ok(())
+    }
+
+    fn account(&self, name: &str) -> Option<&Account> {
+        self.accounts.get(name)
+    }
+}
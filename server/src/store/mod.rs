@@ -0,0 +1,113 @@
+pub mod bans;
+pub mod file;
+pub mod memory;
+
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    error::Result,
+    model::{account::Account, feed::Feed, message::Message},
+};
+
+/// A room as it is remembered across restarts, without its live members.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredRoom {
+    pub id: Uuid,
+    pub name: String,
+}
+
+impl StoredRoom {
+    pub fn new(id: Uuid, name: &str) -> Self {
+        StoredRoom {
+            id,
+            name: String::from(name),
+        }
+    }
+}
+
+/// Feeds each record of the JSON-lines log at `path` to `apply`, in order.
+///
+/// A crash mid-append leaves a torn last line behind; it is logged and cut
+/// off the file so that the next append starts on a line of its own. A bad
+/// line with records after it is corruption and fails the whole replay.
+pub(crate) fn replay_lines<T, P, F>(path: P, mut apply: F) -> Result<()>
+where
+    T: DeserializeOwned,
+    P: AsRef<Path>,
+    F: FnMut(T) -> Result<()>,
+{
+    let path = path.as_ref();
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let contents = fs::read(path)?;
+    let blank = |line: &[u8]| line.iter().all(u8::is_ascii_whitespace);
+    let mut lines = contents.split(|byte| *byte == b'\n');
+    let mut offset = 0;
+    while let Some(line) = lines.next() {
+        let start = offset;
+        offset += line.len() + 1;
+        if blank(line) {
+            continue;
+        }
+        match serde_json::from_slice(line) {
+            Ok(record) => apply(record)?,
+            Err(err) if lines.clone().all(blank) => {
+                warn!("Dropping torn last line of {}: {}", path.display(), err);
+                OpenOptions::new()
+                    .write(true)
+                    .open(path)?
+                    .set_len(start as u64)?;
+                return Ok(());
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    if matches!(contents.last(), Some(byte) if *byte != b'\n') {
+        OpenOptions::new()
+            .append(true)
+            .open(path)?
+            .write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Backend holding the rooms, their message history and registered accounts.
+pub trait FeedStore: Send + Sync {
+    fn add_room(&mut self, room: StoredRoom) -> Result<()>;
+
+    fn rooms(&self) -> Vec<StoredRoom>;
+
+    fn add_message(&mut self, message: Message) -> Result<()>;
+
+    fn edit_message(&mut self, id: &Uuid, text: &str, edited_at_utc: DateTime<Utc>) -> Result<()>;
+
+    fn delete_message(&mut self, id: &Uuid) -> Result<()>;
+
+    fn react(&mut self, id: &Uuid, emoji: &str, user_id: Uuid) -> Result<()>;
+
+    fn unreact(&mut self, id: &Uuid, emoji: &str, user_id: Uuid) -> Result<()>;
+
+    fn message(&self, id: &Uuid) -> Option<&Message>;
+
+    fn feed(&self, room_id: &Uuid) -> Option<&Feed>;
+
+    /// The newest `limit` messages mentioning `user_id` across all rooms,
+    /// oldest first. Deleted messages are left out.
+    fn mentions(&self, user_id: &Uuid, limit: usize) -> Vec<&Message>;
+
+    fn add_account(&mut self, account: Account) -> Result<()>;
+
+    fn account(&self, name: &str) -> Option<&Account>;
+}
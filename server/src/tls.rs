@@ -0,0 +1,185 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use tokio_rustls::{
+    rustls::{
+        server::{ClientHello, ResolvesServerCert},
+        sign::{self, CertifiedKey},
+        Certificate, PrivateKey, ServerConfig,
+    },
+    TlsAcceptor,
+};
+
+use crate::error::{Error, Result};
+
+/// The certificate served on `wss://` connections, re-read from disk on
+/// `reload`. Only new handshakes see a reloaded certificate, so sessions
+/// that are already established are left alone.
+pub struct ReloadableCertificate {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadableCertificate {
+    pub fn load<P: AsRef<Path>>(cert_path: P, key_path: P) -> Result<Self> {
+        let cert_path = cert_path.as_ref().to_path_buf();
+        let key_path = key_path.as_ref().to_path_buf();
+        let current = read_certified_key(&cert_path, &key_path)?;
+        Ok(ReloadableCertificate {
+            cert_path,
+            key_path,
+            current: RwLock::new(Arc::new(current)),
+        })
+    }
+
+    /// Keeps serving the previous certificate if the new one cannot be read.
+    pub fn reload(&self) -> Result<()> {
+        let reloaded = read_certified_key(&self.cert_path, &self.key_path)?;
+        *self.current.write().unwrap() = Arc::new(reloaded);
+        Ok(())
+    }
+
+    pub fn acceptor(self: Arc<Self>) -> TlsAcceptor {
+        let mut config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(self);
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        TlsAcceptor::from(Arc::new(config))
+    }
+}
+
+impl ResolvesServerCert for ReloadableCertificate {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+fn read_certified_key(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey> {
+    let invalid = |path: &Path, err: &dyn std::fmt::Display| {
+        Error::Config(format!("{}: {}", path.display(), err))
+    };
+
+    let mut reader = BufReader::new(File::open(cert_path).map_err(|err| invalid(cert_path, &err))?);
+    let certs = rustls_pemfile::certs(&mut reader).map_err(|err| invalid(cert_path, &err))?;
+    if certs.is_empty() {
+        return Err(invalid(cert_path, &"no certificates found"));
+    }
+
+    let mut reader = BufReader::new(File::open(key_path).map_err(|err| invalid(key_path, &err))?);
+    let key = loop {
+        match rustls_pemfile::read_one(&mut reader).map_err(|err| invalid(key_path, &err))? {
+            Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => break key,
+            Some(_) => continue,
+            None => return Err(invalid(key_path, &"no private key found")),
+        }
+    };
+    let signing_key =
+        sign::any_supported_type(&PrivateKey(key)).map_err(|err| invalid(key_path, &err))?;
+
+    Ok(CertifiedKey::new(
+        certs.into_iter().map(Certificate).collect(),
+        signing_key,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::TryFrom, env, fs, sync::Arc};
+
+    use tokio::{
+        io::{self, AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        runtime::Runtime,
+    };
+    use tokio_rustls::{
+        client::TlsStream,
+        rustls::{ClientConfig, RootCertStore, ServerName},
+        TlsConnector,
+    };
+    use uuid::Uuid;
+
+    use super::*;
+
+    /// Writes a fresh self-signed certificate for localhost, returning its DER.
+    fn write_self_signed(cert_path: &Path, key_path: &Path) -> Vec<u8> {
+        let cert = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+        fs::write(cert_path, cert.serialize_pem().unwrap()).unwrap();
+        fs::write(key_path, cert.serialize_private_key_pem()).unwrap();
+        let pem = fs::read(cert_path).unwrap();
+        rustls_pemfile::certs(&mut pem.as_slice())
+            .unwrap()
+            .remove(0)
+    }
+
+    async fn connect(port: u16, trusted: &[u8]) -> TlsStream<TcpStream> {
+        let mut roots = RootCertStore::empty();
+        roots.add(&Certificate(trusted.to_vec())).unwrap();
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        TlsConnector::from(Arc::new(config))
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+            .unwrap()
+    }
+
+    async fn echo(stream: &mut TlsStream<TcpStream>, byte: u8) -> u8 {
+        stream.write_all(&[byte]).await.unwrap();
+        let mut echoed = [0];
+        stream.read_exact(&mut echoed).await.unwrap();
+        echoed[0]
+    }
+
+    #[test]
+    fn reloads_certificate_for_new_handshakes_only() {
+        let dir = env::temp_dir().join(format!("tls-{}", Uuid::new_v4()));
+        fs::create_dir(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        let first = write_self_signed(&cert_path, &key_path);
+        let certificate = Arc::new(ReloadableCertificate::load(&cert_path, &key_path).unwrap());
+
+        Runtime::new().unwrap().block_on(async {
+            let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let acceptor = certificate.clone().acceptor();
+            tokio::spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let acceptor = acceptor.clone();
+                    tokio::spawn(async move {
+                        let stream = acceptor.accept(stream).await.unwrap();
+                        let (mut reader, mut writer) = io::split(stream);
+                        let _ = io::copy(&mut reader, &mut writer).await;
+                    });
+                }
+            });
+
+            let mut established = connect(port, &first).await;
+            assert_eq!(echo(&mut established, 1).await, 1);
+
+            let second = write_self_signed(&cert_path, &key_path);
+            certificate.reload().unwrap();
+            let mut reconnected = connect(port, &second).await;
+            let (_, session) = reconnected.get_ref();
+            assert_eq!(session.peer_certificates().unwrap()[0].0, second);
+            assert_eq!(echo(&mut reconnected, 2).await, 2);
+
+            // The session opened before the reload keeps working
+            assert_eq!(echo(&mut established, 3).await, 3);
+        });
+
+        fs::write(&key_path, "").unwrap();
+        assert!(certificate.reload().is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,66 +1,361 @@
 use crate::{
-    model::{feed::Feed, message::Message, user::User},
+    auth::{
+        password::{hash_password, verify_password, MIN_PASSWORD_LENGTH},
+        Identity,
+    },
+    client::Client,
+    config::ServerConfig,
+    error::{Error, Result},
+    model::{
+        account::Account,
+        ban::Ban,
+        direct_message::{Conversations, DirectMessage},
+        feed::Feed,
+        message::Message,
+        room::Room,
+        search::SearchQuery,
+        session::{Resumable, Sessions},
+        user::{Role, User},
+    },
+    outbox::{
+        self, Delivery, Inbox, Outbox, OverflowCounters, OverflowPolicy, KICKED_CLOSE_CODE,
+        PROTOCOL_ERROR_CLOSE_CODE, RATE_LIMITED_CLOSE_CODE,
+    },
     protocol::{
-        request::{JoinRequestData, PostMessageRequestData, RequestData, RequestMessage},
+        request::{
+            BanRequestData, CreateRoomRequestData, CredentialsRequestData,
+            DeleteMessageRequestData, DirectMessageRequestData, EditMessageRequestData,
+            FetchDirectMessagesRequestData, FetchHistoryRequestData, FetchThreadRequestData,
+            JoinRequestData, KickRequestData, MarkReadRequestData, MuteRequestData,
+            PostMessageRequestData, ReactionRequestData, RequestData, RequestMessage,
+            ResumeRequestData, RoomRequestData, SearchRequestData,
+        },
         response::{
-            ErrorType, JoinedResponse, MessageResponse, PostedResponse, ResponseData,
-            ResponseMessage, UserJoinedResponse, UserLeftResponse, UserResponse,
+            AnnouncementResponse, DirectMessageResponse, DirectMessagesResponse, ErrorType,
+            HistoryResponse, JoinedResponse, MentionsResponse, MessageDeletedResponse,
+            MessageResponse, PostedResponse, ReactionResponse, ReadReceiptResponse, ResponseData,
+            ResponseMessage, ResumedResponse, RoomJoinedResponse, RoomLeftResponse, RoomResponse,
+            RoomsResponse, SearchResultsResponse, ThreadResponse, UserBannedResponse,
+            UserJoinedResponse, UserJoinedRoomResponse, UserLeftResponse, UserLeftRoomResponse,
+            UserMutedResponse, UserResponse, UserTypingResponse, WelcomeResponse,
         },
     },
+    rate_limit::{RateLimiter, Subject},
+    store::{bans::BanList, memory::MemoryFeedStore, FeedStore, StoredRoom},
 };
-use chrono::Utc;
-use futures::StreamExt;
+use chrono::{Duration as ChronoDuration, Utc};
+use futures::future;
+use log::{debug, error, warn};
 use regex::Regex;
-use std::{collections::HashMap, time::Duration};
-use tokio::sync::{RwLock, broadcast, mpsc::UnboundedReceiver};
-use tokio::time;
-use tokio_stream::wrappers::UnboundedReceiverStream;
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+use tokio::sync::{
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+    Mutex, RwLock,
+};
+use tokio::{task, time};
 use uuid::Uuid;
 
-lazy_static! {
-    static ref USER_NAME_REGEX: Regex = Regex::new("[A-Za-z\\s]{4,24}").unwrap();
+pub const DEFAULT_ROOM_NAME: &str = "general";
+/// Messages sent on join; older ones are fetched page by page.
+pub const HISTORY_PAGE_SIZE: usize = 50;
+pub const MAX_HISTORY_PAGE_SIZE: usize = 200;
+/// How long a dropped session can be resumed before its user is gone.
+pub const RESUME_GRACE_PERIOD: Duration = Duration::from_secs(30);
+const SESSION_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+pub const TYPING_TIMEOUT: Duration = Duration::from_secs(5);
+/// Longest reaction accepted, in characters; enough for joined emoji sequences.
+pub const MAX_REACTION_LENGTH: usize = 16;
+
+/// A password hashed or verified on the blocking pool, with what is needed
+/// to finish signing its client in.
+struct CheckedCredentials {
+    client_id: Uuid,
+    request_id: Option<String>,
+    credentials: Result<Credentials>,
+}
+
+enum Credentials {
+    Registering { name: String, password_hash: String },
+    LoggingIn { account: Account, verified: bool },
+}
+
+tokio::task_local! {
+    /// Id the client gave the request being processed, echoed by `reply`.
+    static REQUEST_ID: Option<String>;
 }
 
 pub struct Worker {
     pub alive_interval: Option<Duration>,
-    pub response_sender: broadcast::Sender<ResponseMessage>,
+    /// Outbound queue of every connected client, keyed by client id.
+    pub outboxes: std::sync::RwLock<HashMap<Uuid, Outbox>>,
+    pub outbox_capacity: usize,
+    pub overflow_policy: OverflowPolicy,
+    pub overflow_counters: OverflowCounters,
     pub users: RwLock<HashMap<Uuid, User>>,
-    pub feed: RwLock<Feed>,
+    pub rooms: RwLock<HashMap<Uuid, Room>>,
+    pub default_room_id: Uuid,
+    pub conversations: RwLock<Conversations>,
+    pub feed: RwLock<Box<dyn FeedStore>>,
+    pub identities: RwLock<HashMap<Uuid, Identity>>,
+    pub sessions: RwLock<Sessions>,
+    pub resume_grace_period: Duration,
+    /// When each user last said they were typing, keyed by room and user id.
+    pub typing: RwLock<HashMap<(Uuid, Uuid), Instant>>,
+    pub typing_timeout: Duration,
+    /// Last message each user has read, keyed by room and user id.
+    pub read_pointers: RwLock<HashMap<(Uuid, Uuid), Uuid>>,
+    /// Names given the moderator role when a token vouches for them.
+    pub moderators: HashSet<String>,
+    /// Accounts given the moderator role, whatever their name.
+    pub moderator_accounts: HashSet<Uuid>,
+    /// When each muted user may post again, keyed by name like bans, so that
+    /// joining again does not lift it.
+    pub mutes: RwLock<HashMap<String, Instant>>,
+    pub bans: RwLock<BanList>,
+    /// Address each client connected from, when known.
+    pub addresses: RwLock<HashMap<Uuid, IpAddr>>,
+    pub rate_limiter: RwLock<RateLimiter>,
+    pub rate_limit_strikes: u32,
+    pub max_invalid_frames: u32,
+    pub user_name_regex: Regex,
+    pub room_name_regex: Regex,
+    pub history_page_size: usize,
+    pub max_history_page_size: usize,
+    /// Credential checks hand their outcome back to `run` through these.
+    checked_sender: UnboundedSender<CheckedCredentials>,
+    checked_receiver: Mutex<UnboundedReceiver<CheckedCredentials>>,
 }
 
 impl Worker {
     pub fn new(duration: Option<Duration>) -> Self {
-        let (sender, _) = broadcast::channel(16);
-        Worker {
+        Self::with_store(duration, Box::new(MemoryFeedStore::default()))
+            .expect("in-memory feed store cannot fail")
+    }
+
+    pub fn with_store(duration: Option<Duration>, store: Box<dyn FeedStore>) -> Result<Self> {
+        let worker = Self::with_config(&ServerConfig::default(), store)?;
+        Ok(Worker {
             alive_interval: duration,
-            response_sender: sender,
+            ..worker
+        })
+    }
+
+    /// Creates a worker whose rooms and history are rebuilt from `store`.
+    pub fn with_config(config: &ServerConfig, mut store: Box<dyn FeedStore>) -> Result<Self> {
+        let default_room_id = match store
+            .rooms()
+            .iter()
+            .find(|room| room.name == DEFAULT_ROOM_NAME)
+        {
+            Some(room) => room.id,
+            None => {
+                let room = StoredRoom::new(Uuid::new_v4(), DEFAULT_ROOM_NAME);
+                let room_id = room.id;
+                store.add_room(room)?;
+                room_id
+            }
+        };
+        let rooms = store
+            .rooms()
+            .iter()
+            .map(|room| (room.id, Room::new(room.id, &room.name)))
+            .collect();
+        let bans = match &config.bans_path {
+            Some(path) => BanList::open(path)?,
+            None => BanList::default(),
+        };
+        let (checked_sender, checked_receiver) = mpsc::unbounded_channel();
+        Ok(Worker {
+            alive_interval: config.alive_interval(),
+            outboxes: Default::default(),
+            outbox_capacity: config.outbox_capacity,
+            overflow_policy: config.overflow_policy,
+            overflow_counters: Default::default(),
             users: Default::default(),
-            feed: Default::default(),
-        }
+            rooms: RwLock::new(rooms),
+            default_room_id,
+            conversations: Default::default(),
+            feed: RwLock::new(store),
+            identities: Default::default(),
+            sessions: Default::default(),
+            resume_grace_period: config.resume_grace_period(),
+            typing: Default::default(),
+            typing_timeout: config.typing_timeout(),
+            read_pointers: Default::default(),
+            moderators: config.moderators.iter().cloned().collect(),
+            moderator_accounts: config.moderator_accounts.iter().copied().collect(),
+            mutes: Default::default(),
+            bans: RwLock::new(bans),
+            addresses: Default::default(),
+            rate_limiter: RwLock::new(RateLimiter::new(
+                config.rate_limits.clone(),
+                config.address_rate_limits.clone(),
+            )),
+            rate_limit_strikes: config.rate_limit_strikes,
+            max_invalid_frames: config.max_invalid_frames,
+            user_name_regex: config.user_name_regex()?,
+            room_name_regex: config.room_name_regex()?,
+            history_page_size: config.history_page_size,
+            max_history_page_size: config.max_history_page_size,
+            checked_sender,
+            checked_receiver: Mutex::new(checked_receiver),
+        })
     }
 
     pub async fn run(&self, receiver: UnboundedReceiver<RequestMessage>) {
         let ticking_alive = self.tick_alive();
-        let stream = UnboundedReceiverStream::new(receiver);
-        let processing = stream.for_each(|input_parcel| self.process(input_parcel));
+        let expiring_sessions = self.tick_sessions();
+        let expiring_typing = self.tick_typing();
+        let processing = self.process_all(receiver);
         tokio::select! {
           _ = ticking_alive => (),
+          _ = expiring_sessions => (),
+          _ = expiring_typing => (),
           _ = processing => ()
         };
     }
 
-    pub fn subscribe(&self) -> broadcast::Receiver<ResponseMessage> {
-        self.response_sender.subscribe()
+    /// Registers `client` and returns the queue of responses addressed to it.
+    pub async fn on_connect(&self, client: &Client) -> Inbox {
+        if let Some(identity) = &client.identity {
+            self.identities
+                .write()
+                .await
+                .insert(client.id, identity.clone());
+        }
+        if let Some(address) = client.address {
+            self.addresses.write().await.insert(client.id, address);
+        }
+        let (outbox, inbox) = outbox::channel(self.outbox_capacity, self.overflow_policy);
+        self.outboxes.write().unwrap().insert(client.id, outbox);
+        inbox
     }
 
     pub async fn on_disconnect(&self, client_id: Uuid) {
-        if self.users.write().await.remove(&client_id).is_some() {
-            self.send_message_to_other_clients(
-                client_id,
-                ResponseData::UserLeft(UserLeftResponse::new(client_id)),
-            )
+        self.outboxes.write().unwrap().remove(&client_id);
+        self.identities.write().await.remove(&client_id);
+        let address = self.addresses.write().await.remove(&client_id);
+        self.rate_limiter.write().await.forget(&client_id);
+        let user = self.users.write().await.remove(&client_id);
+        if let Some(user) = user {
+            let user_id = user.id;
+            let room_ids = self.leave_rooms(client_id).await;
+            // The user only leaves once the grace period runs out without a resume
+            let suspended = self
+                .sessions
+                .write()
+                .await
+                .suspend(client_id, user, room_ids, address);
+            if !suspended && !self.is_online(&user_id).await {
+                self.send_message_to_other_clients(
+                    client_id,
+                    ResponseData::UserLeft(UserLeftResponse::new(user_id)),
+                )
+                .await;
+            }
+        }
+    }
+
+    /// Closes every session of `user_id` for good, leaving nothing to
+    /// resume. Returns how many sessions were closed.
+    pub async fn kick(&self, user_id: Uuid) -> usize {
+        let client_ids: Vec<Uuid> = self
+            .users
+            .read()
+            .await
+            .iter()
+            .filter(|(_, user)| user.id == user_id)
+            .map(|(client_id, _)| *client_id)
+            .collect();
+        self.sessions.write().await.revoke(&user_id, &client_ids);
+        self.close_clients(&client_ids, KICKED_CLOSE_CODE, "kicked")
             .await;
+        client_ids.len()
+    }
+
+    /// Answers a frame that is not a valid request, and closes the
+    /// connection once `consecutive` such frames reach `max_invalid_frames`.
+    pub fn reject_frame(&self, client_id: Uuid, error_type: ErrorType, consecutive: u32) {
+        self.send_error(client_id, error_type);
+        if consecutive >= self.max_invalid_frames {
+            warn!("Disconnecting client {} for invalid requests", client_id);
+            if let Some(outbox) = self.outboxes.read().unwrap().get(&client_id) {
+                outbox.close(PROTOCOL_ERROR_CLOSE_CODE, "invalid requests");
+            }
+        }
+    }
+
+    /// Tells `client_id` which protocol was settled on.
+    pub fn welcome(&self, client_id: Uuid, welcome: WelcomeResponse) {
+        self.send_message_to_client(client_id, ResponseData::Welcome(welcome));
+    }
+
+    /// Whether connections from `address` are refused.
+    pub async fn is_banned(&self, address: &IpAddr) -> bool {
+        self.bans.read().await.contains(&Ban::Address(*address))
+    }
+
+    async fn close_clients(&self, client_ids: &[Uuid], code: u16, reason: &'static str) {
+        for client_id in client_ids {
+            if let Some(outbox) = self.outboxes.read().unwrap().get(client_id) {
+                outbox.close(code, reason);
+            }
+            self.on_disconnect(*client_id).await;
+        }
+    }
+
+    /// Sends a system announcement to every joined client.
+    pub async fn announce(&self, text: &str) {
+        self.send(ResponseData::Announcement(AnnouncementResponse::new(
+            text,
+            Utc::now(),
+        )))
+        .await;
+    }
+
+    /// Deletes any message, whoever wrote it, on behalf of an operator.
+    pub async fn remove_message(&self, message_id: Uuid) -> std::result::Result<(), ErrorType> {
+        let room_id = {
+            let mut store = self.feed.write().await;
+            let room_id = match store.message(&message_id) {
+                Some(message) if !message.deleted => message.room_id,
+                _ => return Err(ErrorType::MessageNotFound),
+            };
+            if let Err(err) = store.delete_message(&message_id) {
+                error!("Failed to delete message {}: {}", message_id, err);
+                return Err(ErrorType::StorageFailed);
+            }
+            room_id
+        };
+
+        if let Some(room) = self.rooms.read().await.get(&room_id) {
+            self.deliver(
+                &room.members,
+                ResponseData::MessageDeleted(MessageDeletedResponse::new(room_id, message_id)),
+            );
         }
+        Ok(())
+    }
+
+    /// Removes `client_id` from its rooms and returns their ids.
+    async fn leave_rooms(&self, client_id: Uuid) -> Vec<Uuid> {
+        self.rooms
+            .write()
+            .await
+            .values_mut()
+            .filter_map(|room| {
+                if room.members.remove(&client_id) {
+                    Some(room.id)
+                } else {
+                    None
+                }
+            })
+            .collect()
     }
 
     async fn tick_alive(&self) {
@@ -69,11 +364,69 @@ impl Worker {
                 time::sleep(interval).await;
                 self.send(ResponseData::Alive).await;
             },
-            None => return,
+            // Nothing to tick, but returning would end `run` along with it
+            None => future::pending().await,
+        }
+    }
+
+    async fn tick_sessions(&self) {
+        loop {
+            time::sleep(SESSION_SWEEP_INTERVAL).await;
+            let expired = self.sessions.write().await.expire(self.resume_grace_period);
+            self.rate_limiter.write().await.prune(Instant::now());
+            for session in expired {
+                // Other sessions of the same account keep the user online
+                if !self.is_online(&session.user.id).await {
+                    self.send(ResponseData::UserLeft(UserLeftResponse::new(
+                        session.user.id,
+                    )))
+                    .await;
+                }
+            }
+        }
+    }
+
+    /// Expires typing indicators as their timeout runs out, waking for the
+    /// oldest one, or once a timeout when nobody is typing.
+    async fn tick_typing(&self) {
+        loop {
+            let oldest = self.typing.read().await.values().min().copied();
+            let deadline = oldest.unwrap_or_else(Instant::now) + self.typing_timeout;
+            time::sleep_until(time::Instant::from_std(deadline)).await;
+            self.expire_typing().await;
+        }
+    }
+
+    /// Processes requests one at a time, along with credential checks as
+    /// they finish, until the request channel closes.
+    async fn process_all(&self, mut receiver: UnboundedReceiver<RequestMessage>) {
+        let mut checked_receiver = self.checked_receiver.lock().await;
+        loop {
+            tokio::select! {
+                request_message = receiver.recv() => match request_message {
+                    Some(request_message) => self.process(request_message).await,
+                    None => return,
+                },
+                Some(checked) = checked_receiver.recv() => self.finish_sign_in(checked).await,
+            }
         }
     }
 
     async fn process(&self, request_message: RequestMessage) {
+        let request_id = request_message.request_id.clone();
+        REQUEST_ID
+            .scope(request_id, self.dispatch(request_message))
+            .await
+    }
+
+    async fn dispatch(&self, request_message: RequestMessage) {
+        if !self
+            .within_rate_limit(request_message.client_id, &request_message.request_data)
+            .await
+        {
+            return;
+        }
+
         match request_message.request_data {
             RequestData::Join(request) => {
                 self.process_join(request_message.client_id, request).await
@@ -81,223 +434,3742 @@ impl Worker {
             RequestData::PostMessage(request) => {
                 self.process_post(request_message.client_id, request).await
             }
+            RequestData::CreateRoom(request) => {
+                self.process_create_room(request_message.client_id, request)
+                    .await
+            }
+            RequestData::ListRooms => self.process_list_rooms(request_message.client_id).await,
+            RequestData::JoinRoom(request) => {
+                self.process_join_room(request_message.client_id, request)
+                    .await
+            }
+            RequestData::LeaveRoom(request) => {
+                self.process_leave_room(request_message.client_id, request)
+                    .await
+            }
+            RequestData::DirectMessage(request) => {
+                self.process_direct_message(request_message.client_id, request)
+                    .await
+            }
+            RequestData::FetchDirectMessages(request) => {
+                self.process_fetch_direct_messages(request_message.client_id, request)
+                    .await
+            }
+            RequestData::FetchHistory(request) => {
+                self.process_fetch_history(request_message.client_id, request)
+                    .await
+            }
+            RequestData::EditMessage(request) => {
+                self.process_edit_message(request_message.client_id, request)
+                    .await
+            }
+            RequestData::DeleteMessage(request) => {
+                self.process_delete_message(request_message.client_id, request)
+                    .await
+            }
+            RequestData::Register(request) => {
+                self.process_register(request_message.client_id, request)
+                    .await
+            }
+            RequestData::Login(request) => {
+                self.process_login(request_message.client_id, request).await
+            }
+            RequestData::Resume(request) => {
+                self.process_resume(request_message.client_id, request)
+                    .await
+            }
+            RequestData::StartTyping(request) => {
+                self.process_typing(request_message.client_id, request, true)
+                    .await
+            }
+            RequestData::StopTyping(request) => {
+                self.process_typing(request_message.client_id, request, false)
+                    .await
+            }
+            RequestData::MarkRead(request) => {
+                self.process_mark_read(request_message.client_id, request)
+                    .await
+            }
+            RequestData::React(request) => {
+                self.process_reaction(request_message.client_id, request, true)
+                    .await
+            }
+            RequestData::Unreact(request) => {
+                self.process_reaction(request_message.client_id, request, false)
+                    .await
+            }
+            RequestData::FetchThread(request) => {
+                self.process_fetch_thread(request_message.client_id, request)
+                    .await
+            }
+            RequestData::FetchMentions => {
+                self.process_fetch_mentions(request_message.client_id).await
+            }
+            RequestData::Search(request) => {
+                self.process_search(request_message.client_id, request)
+                    .await
+            }
+            RequestData::Mute(request) => {
+                self.process_mute(request_message.client_id, request).await
+            }
+            RequestData::Kick(request) => {
+                self.process_kick(request_message.client_id, request).await
+            }
+            RequestData::Ban(request) => self.process_ban(request_message.client_id, request).await,
+            // The client answers a `Hello` sent first, so this one came too late
+            RequestData::Hello(_) => self.send_error(
+                request_message.client_id,
+                ErrorType::invalid_request("hello must be the first request"),
+            ),
         }
     }
 
+    /// Whether `request_data` fits the rate limits of its client and of the
+    /// client's address. Requests over them are answered with `RateLimited`,
+    /// and clients that keep going over their own limits are disconnected.
+    async fn within_rate_limit(&self, client_id: Uuid, request_data: &RequestData) -> bool {
+        let mut subjects = vec![Subject::Client(client_id)];
+        if let Some(address) = self.addresses.read().await.get(&client_id) {
+            subjects.push(Subject::Address(*address));
+        }
+
+        let now = Instant::now();
+        let strikes = {
+            let mut rate_limiter = self.rate_limiter.write().await;
+            match rate_limiter.check(request_data.kind(), &subjects, now) {
+                Ok(()) => return true,
+                Err(limited) => {
+                    self.send_error(
+                        client_id,
+                        ErrorType::RateLimited {
+                            retry_after_ms: limited.retry_after.as_micros().div_ceil(1000) as u64,
+                        },
+                    );
+                    // Clients behind the address of a flooder are not to blame
+                    match limited.subject {
+                        Subject::Client(_) => rate_limiter.strike(client_id, now),
+                        Subject::Address(_) => return false,
+                    }
+                }
+            }
+        };
+
+        if strikes >= self.rate_limit_strikes {
+            warn!("Disconnecting client {} for flooding", client_id);
+            self.close_clients(&[client_id], RATE_LIMITED_CLOSE_CODE, "rate limited")
+                .await;
+        }
+        false
+    }
+
     async fn process_join(&self, client_id: Uuid, join_request_data: JoinRequestData) {
-        let user_name = join_request_data.name.trim();
+        if self.users.read().await.contains_key(&client_id) {
+            self.send_error(client_id, ErrorType::AlreadyJoined);
+            return;
+        }
+
+        let user_name = match self.session_name(client_id, &join_request_data.name).await {
+            Ok(user_name) => user_name,
+            Err(error_type) => {
+                self.send_error(client_id, error_type);
+                return;
+            }
+        };
+        let user_name = user_name.as_str();
+
         if self
             .users
             .read()
             .await
             .values()
             .any(|user| user.name == user_name)
+            || self.feed.read().await.account(user_name).is_some()
         {
             self.send_error(client_id, ErrorType::NameExisted);
             return;
         }
 
-        if !USER_NAME_REGEX.is_match(user_name) {
+        if !self.user_name_regex.is_match(user_name) {
             self.send_error(client_id, ErrorType::InvalidName);
             return;
         }
 
-        let user = User::new(client_id, user_name);
-        self.users.write().await.insert(client_id, user);
+        self.join(client_id, User::new(client_id, user_name)).await;
+    }
 
-        let user_response = UserResponse::new(client_id, user_name);
-        let other_users = self
-            .users
-            .read()
-            .await
-            .values()
-            .filter_map(|user| {
-                if user.id != client_id {
-                    Some(UserResponse::new(user.id, &user.name))
-                } else {
-                    None
-                }
-            })
-            .collect();
+    async fn process_register(
+        &self,
+        client_id: Uuid,
+        credentials_request_data: CredentialsRequestData,
+    ) {
+        if self.users.read().await.contains_key(&client_id) {
+            self.send_error(client_id, ErrorType::AlreadyJoined);
+            return;
+        }
 
-        let messages = self
-            .feed
-            .read()
+        let user_name = match self
+            .session_name(client_id, &credentials_request_data.name)
             .await
-            .iter()
-            .map(|message| {
-                MessageResponse::new(
-                    message.id,
-                    UserResponse::new(message.user.id, &message.user.name),
-                    &message.text,
-                    message.created_at_utc,
-                )
+        {
+            Ok(user_name) => user_name,
+            Err(error_type) => {
+                self.send_error(client_id, error_type);
+                return;
+            }
+        };
+
+        if !self.user_name_regex.is_match(&user_name) {
+            self.send_error(client_id, ErrorType::InvalidName);
+            return;
+        }
+
+        let password = credentials_request_data.password;
+        if password.chars().count() < MIN_PASSWORD_LENGTH {
+            self.send_error(client_id, ErrorType::InvalidPassword);
+            return;
+        }
+
+        self.check_credentials(client_id, move || {
+            Ok(Credentials::Registering {
+                name: user_name,
+                password_hash: hash_password(&password)?,
             })
-            .collect();
+        });
+    }
 
-        self.send_message_to_client(
-            client_id,
-            ResponseData::Joined(JoinedResponse::new(
-                user_response.clone(),
-                other_users,
-                messages,
-            )),
-        );
+    /// Stores the account of a hashed registration and joins with it.
+    async fn register(&self, client_id: Uuid, user_name: &str, password_hash: &str) {
+        let account = Account::new(Uuid::new_v4(), user_name, password_hash, Utc::now());
+        {
+            let users = self.users.read().await;
+            let mut store = self.feed.write().await;
+            if store.account(user_name).is_some()
+                || users.values().any(|user| user.name == user_name)
+            {
+                self.send_error(client_id, ErrorType::NameExisted);
+                return;
+            }
+            if let Err(err) = store.add_account(account.clone()) {
+                error!("Failed to store account {}: {}", account.id, err);
+                self.send_error(client_id, ErrorType::StorageFailed);
+                return;
+            }
+        }
 
-        self.send_message_to_other_clients(
-            client_id,
-            ResponseData::UserJoined(UserJoinedResponse::new(user_response)),
-        )
-        .await;
+        self.join(client_id, User::new(account.id, &account.name))
+            .await;
     }
 
-    async fn process_post(
+    async fn process_login(
         &self,
         client_id: Uuid,
-        post_message_request_data: PostMessageRequestData,
+        credentials_request_data: CredentialsRequestData,
     ) {
-        let user = if let Some(user) = self.users.read().await.get(&client_id) {
-            user.clone()
-        } else {
-            self.send_error(client_id, ErrorType::NotJoined);
-            return;
-        };
-
-        if post_message_request_data.text.is_empty() {
-            self.send_error(client_id, ErrorType::InvalidMessage);
+        if self.users.read().await.contains_key(&client_id) {
+            self.send_error(client_id, ErrorType::AlreadyJoined);
             return;
         }
 
-        let message = Message::new(
-            Uuid::new_v4(),
-            user.clone(),
-            &post_message_request_data.text,
-            Utc::now(),
-        );
-        self.feed.write().await.add_message(message.clone());
+        let user_name = match self
+            .session_name(client_id, &credentials_request_data.name)
+            .await
+        {
+            Ok(user_name) => user_name,
+            Err(error_type) => {
+                self.send_error(client_id, error_type);
+                return;
+            }
+        };
 
-        let message_reponse = MessageResponse::new(
-            message.id,
-            UserResponse::new(user.id, &user.name),
-            &message.text,
-            message.created_at_utc,
-        );
+        let account = match self.feed.read().await.account(&user_name) {
+            Some(account) => account.clone(),
+            None => {
+                self.send_error(client_id, ErrorType::InvalidCredentials);
+                return;
+            }
+        };
 
-        self.send_message_to_client(
-            client_id,
-            ResponseData::Posted(PostedResponse::new(message_reponse.clone())),
-        );
+        let password = credentials_request_data.password;
+        self.check_credentials(client_id, move || {
+            let verified = verify_password(&password, &account.password_hash);
+            Ok(Credentials::LoggingIn { account, verified })
+        });
+    }
+
+    /// Runs `check` on the blocking pool, so that hashing a password does not
+    /// hold up requests from everyone else, and hands its outcome to `run`.
+    fn check_credentials<F>(&self, client_id: Uuid, check: F)
+    where
+        F: FnOnce() -> Result<Credentials> + Send + 'static,
+    {
+        let request_id = REQUEST_ID.try_with(Clone::clone).ok().flatten();
+        let checked_sender = self.checked_sender.clone();
+        tokio::spawn(async move {
+            let credentials = match task::spawn_blocking(check).await {
+                Ok(credentials) => credentials,
+                Err(err) => Err(Error::System(err.to_string())),
+            };
+            // Only fails once the worker is gone
+            let _ = checked_sender.send(CheckedCredentials {
+                client_id,
+                request_id,
+                credentials,
+            });
+        });
+    }
 
-        self.send_message_to_other_clients(
+    async fn finish_sign_in(&self, checked: CheckedCredentials) {
+        let CheckedCredentials {
             client_id,
-            ResponseData::UserPosted(PostedResponse::new(message_reponse)),
-        )
-        .await;
+            request_id,
+            credentials,
+        } = checked;
+        let signing_in = async {
+            // The client may have left or joined otherwise while it waited
+            if !self.outboxes.read().unwrap().contains_key(&client_id) {
+                return;
+            }
+            if self.users.read().await.contains_key(&client_id) {
+                self.send_error(client_id, ErrorType::AlreadyJoined);
+                return;
+            }
+            match credentials {
+                Ok(Credentials::Registering {
+                    name,
+                    password_hash,
+                }) => self.register(client_id, &name, &password_hash).await,
+                Ok(Credentials::LoggingIn {
+                    account,
+                    verified: true,
+                }) => {
+                    self.join(client_id, User::new(account.id, &account.name))
+                        .await
+                }
+                Ok(Credentials::LoggingIn { .. }) => {
+                    self.send_error(client_id, ErrorType::InvalidCredentials)
+                }
+                Err(err) => {
+                    error!("Failed to check credentials: {}", err);
+                    self.send_error(client_id, ErrorType::StorageFailed);
+                }
+            }
+        };
+        REQUEST_ID.scope(request_id, signing_in).await
     }
 
-    async fn send(&self, response_data: ResponseData) {
-        if self.response_sender.receiver_count() > 0 {
-            self.users.read().await.keys().for_each(|user_id| {
-                self.response_sender
-                    .send(ResponseMessage::new(*user_id, response_data.clone()))
-                    .unwrap();
-            })
+    async fn process_resume(&self, client_id: Uuid, resume_request_data: ResumeRequestData) {
+        if self.users.read().await.contains_key(&client_id) {
+            self.send_error(client_id, ErrorType::AlreadyJoined);
+            return;
+        }
+        let address = self.addresses.read().await.get(&client_id).copied();
+        if let Some(address) = address {
+            if self.is_banned(&address).await {
+                self.send_error(client_id, ErrorType::Banned);
+                return;
+            }
         }
-    }
 
-    fn send_message_to_client(&self, client_id: Uuid, response_data: ResponseData) {
-        if self.response_sender.receiver_count() > 0 {
-            self.response_sender
-                .send(ResponseMessage::new(client_id, response_data))
-                .unwrap();
+        let resumable = self.sessions.write().await.take(&resume_request_data.token);
+        let (user, room_ids) = match resumable {
+            Some(Resumable::Suspended(session)) => (session.user, session.room_ids),
+            Some(Resumable::Live(old_client_id)) => {
+                // The old connection is taken over without anyone noticing
+                let user = self.users.write().await.remove(&old_client_id);
+                match user {
+                    Some(user) => (user, self.leave_rooms(old_client_id).await),
+                    None => {
+                        self.send_error(client_id, ErrorType::InvalidResumeToken);
+                        return;
+                    }
+                }
+            }
+            None => {
+                self.send_error(client_id, ErrorType::InvalidResumeToken);
+                return;
+            }
+        };
+        // Bans on names only kick live users, leaving suspended ones to this
+        let banned = self
+            .bans
+            .read()
+            .await
+            .contains(&Ban::Name(user.name.clone()));
+        if banned {
+            self.send_error(client_id, ErrorType::Banned);
+            return;
         }
-    }
 
-    async fn send_message_to_other_clients(&self, client_id: Uuid, response_data: ResponseData) {
-        if self.response_sender.receiver_count() > 0 {
+        let user_response = UserResponse::from(&user);
+        self.users.write().await.insert(client_id, user);
+
+        let other_users = Self::distinct_users(
             self.users
                 .read()
                 .await
                 .values()
-                .filter(|user| user.id != client_id)
-                .for_each(|user| {
-                    self.response_sender
-                        .send(ResponseMessage::new(user.id, response_data.clone()))
-                        .unwrap();
-                })
-        }
-    }
+                .filter(|other| other.id != user_response.id),
+        );
 
-    fn send_error(&self, client_id: Uuid, error_type: ErrorType) {
-        self.send_message_to_client(client_id, ResponseData::Error(error_type))
+        let rooms = {
+            let mut rooms = self.rooms.write().await;
+            rooms
+                .values_mut()
+                .filter(|room| room_ids.contains(&room.id))
+                .map(|room| {
+                    room.members.insert(client_id);
+                    RoomResponse::from(&*room)
+                })
+                .collect()
+        };
+        let (messages, truncated_room_ids) = self
+            .missed_messages(&room_ids, resume_request_data.last_seen_message_id)
+            .await;
+        let resume_token = self.sessions.write().await.issue(client_id);
+
+        self.send_message_to_client(
+            client_id,
+            ResponseData::Resumed(ResumedResponse::new(
+                user_response,
+                other_users,
+                rooms,
+                messages,
+                truncated_room_ids,
+                &resume_token,
+            )),
+        );
+    }
+
+    /// The name a client may join under: the one from its token when the
+    /// connection is authenticated, the requested one otherwise.
+    async fn session_name(
+        &self,
+        client_id: Uuid,
+        requested_name: &str,
+    ) -> std::result::Result<String, ErrorType> {
+        let requested_name = requested_name.trim();
+        let bans = self.bans.read().await;
+        match self.identities.read().await.get(&client_id) {
+            Some(identity) if requested_name.is_empty() || requested_name == identity.name => {
+                Ok(identity.name.clone())
+            }
+            Some(_) => Err(ErrorType::NameMismatch),
+            None => Ok(String::from(requested_name)),
+        }
+        .and_then(|name| {
+            if bans.contains(&Ban::Name(name.clone())) {
+                Err(ErrorType::Banned)
+            } else {
+                Ok(name)
+            }
+        })
+    }
+
+    async fn join(&self, client_id: Uuid, mut user: User) {
+        // Anyone can ask for a name, or register it first, so only tokens
+        // vouch for one
+        let vouched = self.identities.read().await.contains_key(&client_id)
+            && self.moderators.contains(&user.name);
+        if vouched || self.moderator_accounts.contains(&user.id) {
+            user.role = Role::Moderator;
+        }
+        let user_response = UserResponse::from(&user);
+        let first_session = !self.is_online(&user.id).await;
+        self.users.write().await.insert(client_id, user);
+
+        let other_users = Self::distinct_users(
+            self.users
+                .read()
+                .await
+                .values()
+                .filter(|other| other.id != user_response.id),
+        );
+
+        let room = {
+            let mut rooms = self.rooms.write().await;
+            let room = rooms.get_mut(&self.default_room_id).unwrap();
+            room.members.insert(client_id);
+            RoomResponse::from(&*room)
+        };
+        let messages = self.latest_room_messages(&room.id).await;
+        let unread_count = self.unread_count(&room.id, &user_response.id).await;
+        let resume_token = self.sessions.write().await.issue(client_id);
+
+        self.reply(
+            client_id,
+            ResponseData::Joined(JoinedResponse::new(
+                user_response.clone(),
+                other_users,
+                room,
+                messages,
+                &resume_token,
+                unread_count,
+            )),
+        );
+
+        if first_session {
+            self.send_message_to_other_clients(
+                client_id,
+                ResponseData::UserJoined(UserJoinedResponse::new(user_response)),
+            )
+            .await;
+        }
+    }
+
+    async fn process_post(
+        &self,
+        client_id: Uuid,
+        post_message_request_data: PostMessageRequestData,
+    ) {
+        let user = if let Some(user) = self.users.read().await.get(&client_id) {
+            user.clone()
+        } else {
+            self.send_error(client_id, ErrorType::NotJoined);
+            return;
+        };
+
+        if self.is_muted(&user.name).await {
+            self.send_error(client_id, ErrorType::Muted);
+            return;
+        }
+
+        if post_message_request_data.text.is_empty() {
+            self.send_error(client_id, ErrorType::InvalidMessage);
+            return;
+        }
+
+        let room_id = post_message_request_data.room_id;
+        match self.rooms.read().await.get(&room_id) {
+            Some(room) if room.is_member(&client_id) => (),
+            Some(_) => {
+                self.send_error(client_id, ErrorType::NotInRoom);
+                return;
+            }
+            None => {
+                self.send_error(client_id, ErrorType::RoomNotFound);
+                return;
+            }
+        }
+
+        let parent_id = match post_message_request_data.reply_to {
+            Some(reply_to) => match self.feed.read().await.message(&reply_to) {
+                // Threads are one level deep, so replying to a reply joins its thread
+                Some(parent) if parent.room_id == room_id && !parent.deleted => {
+                    Some(parent.parent_id.unwrap_or(parent.id))
+                }
+                _ => {
+                    self.send_error(client_id, ErrorType::MessageNotFound);
+                    return;
+                }
+            },
+            None => None,
+        };
+        let mentions = Self::mentioned_user_ids(
+            &post_message_request_data.text,
+            self.users.read().await.values(),
+            user.id,
+        );
+        let message = Message {
+            parent_id,
+            mentions,
+            ..Message::new(
+                Uuid::new_v4(),
+                room_id,
+                user,
+                &post_message_request_data.text,
+                Utc::now(),
+            )
+        };
+
+        if let Err(err) = self.feed.write().await.add_message(message.clone()) {
+            error!("Failed to store message {}: {}", message.id, err);
+            self.send_error(client_id, ErrorType::StorageFailed);
+            return;
+        }
+
+        let message_reponse = MessageResponse::from(&message);
+
+        self.reply(
+            client_id,
+            ResponseData::Posted(PostedResponse::new(message_reponse.clone())),
+        );
+
+        // Posting is the usual end of typing
+        let user_id = message.user.id;
+        if self
+            .typing
+            .write()
+            .await
+            .remove(&(room_id, user_id))
+            .is_some()
+        {
+            self.send_message_to_other_room_members(
+                room_id,
+                client_id,
+                ResponseData::UserTyping(UserTypingResponse::new(room_id, user_id, false)),
+            )
+            .await;
+        }
+
+        self.send_message_to_other_room_members(
+            room_id,
+            client_id,
+            ResponseData::UserPosted(PostedResponse::new(message_reponse.clone())),
+        )
+        .await;
+
+        // Mentioned users hear of it wherever they are, on every session
+        for user_id in &message.mentions {
+            self.send_message_to_user(
+                *user_id,
+                client_id,
+                ResponseData::Mentioned(PostedResponse::new(message_reponse.clone())),
+            )
+            .await;
+        }
+    }
+
+    async fn process_typing(
+        &self,
+        client_id: Uuid,
+        room_request_data: RoomRequestData,
+        typing: bool,
+    ) {
+        let user_id = if let Some(user) = self.users.read().await.get(&client_id) {
+            user.id
+        } else {
+            self.send_error(client_id, ErrorType::NotJoined);
+            return;
+        };
+
+        let room_id = room_request_data.room_id;
+        match self.rooms.read().await.get(&room_id) {
+            Some(room) if room.is_member(&client_id) => (),
+            Some(_) => {
+                self.send_error(client_id, ErrorType::NotInRoom);
+                return;
+            }
+            None => {
+                self.send_error(client_id, ErrorType::RoomNotFound);
+                return;
+            }
+        }
+
+        // Repeated StartTyping only pushes the expiry back
+        let changed = {
+            let mut typing_users = self.typing.write().await;
+            if typing {
+                typing_users
+                    .insert((room_id, user_id), Instant::now())
+                    .is_none()
+            } else {
+                typing_users.remove(&(room_id, user_id)).is_some()
+            }
+        };
+        if changed {
+            self.send_message_to_other_room_members(
+                room_id,
+                client_id,
+                ResponseData::UserTyping(UserTypingResponse::new(room_id, user_id, typing)),
+            )
+            .await;
+        }
+    }
+
+    async fn expire_typing(&self) {
+        let now = Instant::now();
+        let expired: Vec<(Uuid, Uuid)> = {
+            let mut typing_users = self.typing.write().await;
+            let expired = typing_users
+                .iter()
+                .filter(|(_, since)| now.duration_since(**since) >= self.typing_timeout)
+                .map(|(key, _)| *key)
+                .collect();
+            typing_users.retain(|_, since| now.duration_since(*since) < self.typing_timeout);
+            expired
+        };
+        let rooms = self.rooms.read().await;
+        for (room_id, user_id) in expired {
+            if let Some(room) = rooms.get(&room_id) {
+                self.deliver(
+                    &room.members,
+                    ResponseData::UserTyping(UserTypingResponse::new(room_id, user_id, false)),
+                );
+            }
+        }
+    }
+
+    async fn process_fetch_thread(
+        &self,
+        client_id: Uuid,
+        fetch_thread_request_data: FetchThreadRequestData,
+    ) {
+        if !self.users.read().await.contains_key(&client_id) {
+            self.send_error(client_id, ErrorType::NotJoined);
+            return;
+        }
+
+        let root_id = fetch_thread_request_data.root_id;
+        let room_id = match self.feed.read().await.message(&root_id) {
+            Some(root) if root.parent_id.is_none() => root.room_id,
+            _ => {
+                self.send_error(client_id, ErrorType::MessageNotFound);
+                return;
+            }
+        };
+        match self.rooms.read().await.get(&room_id) {
+            Some(room) if room.is_member(&client_id) => (),
+            Some(_) => {
+                self.send_error(client_id, ErrorType::NotInRoom);
+                return;
+            }
+            None => {
+                self.send_error(client_id, ErrorType::RoomNotFound);
+                return;
+            }
+        }
+
+        let thread = {
+            let store = self.feed.read().await;
+            let feed = store.feed(&room_id).unwrap();
+            let replies = feed
+                .replies(&root_id)
+                .into_iter()
+                .map(MessageResponse::from)
+                .collect();
+            ThreadResponse::new(MessageResponse::from(feed.get(&root_id).unwrap()), replies)
+        };
+
+        self.send_message_to_client(client_id, ResponseData::Thread(thread));
+    }
+
+    async fn process_fetch_mentions(&self, client_id: Uuid) {
+        let user_id = if let Some(user) = self.users.read().await.get(&client_id) {
+            user.id
+        } else {
+            self.send_error(client_id, ErrorType::NotJoined);
+            return;
+        };
+
+        let messages = self
+            .feed
+            .read()
+            .await
+            .mentions(&user_id, self.history_page_size)
+            .into_iter()
+            .map(MessageResponse::from)
+            .collect();
+
+        self.send_message_to_client(
+            client_id,
+            ResponseData::Mentions(MentionsResponse::new(messages)),
+        );
+    }
+
+    async fn process_search(&self, client_id: Uuid, search_request_data: SearchRequestData) {
+        if !self.users.read().await.contains_key(&client_id) {
+            self.send_error(client_id, ErrorType::NotJoined);
+            return;
+        }
+
+        let query = match SearchQuery::parse(&search_request_data.query) {
+            Some(query) => query,
+            None => {
+                self.send_error(client_id, ErrorType::InvalidQuery);
+                return;
+            }
+        };
+
+        // Only rooms the client is in are searched
+        let room_ids: Vec<Uuid> = self
+            .rooms
+            .read()
+            .await
+            .values()
+            .filter(|room| room.is_member(&client_id))
+            .map(|room| room.id)
+            .collect();
+        let limit = search_request_data.limit.min(self.max_history_page_size);
+        let messages = {
+            let store = self.feed.read().await;
+            let is_wanted = |message: &&Message| {
+                search_request_data
+                    .from_user
+                    .is_none_or(|user_id| message.user.id == user_id)
+                    && search_request_data
+                        .before
+                        .is_none_or(|before| message.created_at_utc < before)
+                    && search_request_data
+                        .after
+                        .is_none_or(|after| message.created_at_utc > after)
+            };
+            let mut messages: Vec<&Message> = room_ids
+                .iter()
+                .filter_map(|room_id| store.feed(room_id))
+                .flat_map(|feed| feed.search(&query).filter(is_wanted).take(limit))
+                .collect();
+            messages.sort_by_key(|message| Reverse((message.created_at_utc, message.id)));
+            messages
+                .into_iter()
+                .take(limit)
+                .map(MessageResponse::from)
+                .collect()
+        };
+
+        self.send_message_to_client(
+            client_id,
+            ResponseData::SearchResults(SearchResultsResponse::new(
+                &search_request_data.query,
+                messages,
+            )),
+        );
+    }
+
+    async fn process_mark_read(
+        &self,
+        client_id: Uuid,
+        mark_read_request_data: MarkReadRequestData,
+    ) {
+        let user_id = if let Some(user) = self.users.read().await.get(&client_id) {
+            user.id
+        } else {
+            self.send_error(client_id, ErrorType::NotJoined);
+            return;
+        };
+
+        let message_id = mark_read_request_data.message_id;
+        let (room_id, position) = match self.feed.read().await.message(&message_id) {
+            Some(message) => (message.room_id, (message.created_at_utc, message.id)),
+            None => {
+                self.send_error(client_id, ErrorType::MessageNotFound);
+                return;
+            }
+        };
+        match self.rooms.read().await.get(&room_id) {
+            Some(room) if room.is_member(&client_id) => (),
+            Some(_) => {
+                self.send_error(client_id, ErrorType::NotInRoom);
+                return;
+            }
+            None => {
+                self.send_error(client_id, ErrorType::RoomNotFound);
+                return;
+            }
+        }
+
+        // The pointer only moves forward, whichever session reports last
+        let advanced = {
+            let store = self.feed.read().await;
+            let mut read_pointers = self.read_pointers.write().await;
+            let last_read = read_pointers
+                .get(&(room_id, user_id))
+                .and_then(|id| store.message(id))
+                .map(|message| (message.created_at_utc, message.id));
+            match last_read {
+                Some(last_read) if last_read >= position => false,
+                _ => {
+                    read_pointers.insert((room_id, user_id), message_id);
+                    true
+                }
+            }
+        };
+        if advanced {
+            self.send_message_to_other_room_members(
+                room_id,
+                client_id,
+                ResponseData::ReadReceipt(ReadReceiptResponse::new(room_id, user_id, message_id)),
+            )
+            .await;
+        }
+    }
+
+    /// Messages by other users in `room_id` that `user_id` has not read yet.
+    async fn unread_count(&self, room_id: &Uuid, user_id: &Uuid) -> usize {
+        let last_read = self
+            .read_pointers
+            .read()
+            .await
+            .get(&(*room_id, *user_id))
+            .copied();
+        let store = self.feed.read().await;
+        let feed = match store.feed(room_id) {
+            Some(feed) => feed,
+            None => return 0,
+        };
+        let unread = match last_read.and_then(|id| feed.after(&id)) {
+            Some(unread) => unread,
+            None => feed.latest(usize::MAX),
+        };
+        unread
+            .iter()
+            .filter(|message| !message.deleted && message.user.id != *user_id)
+            .count()
+    }
+
+    async fn process_create_room(
+        &self,
+        client_id: Uuid,
+        create_room_request_data: CreateRoomRequestData,
+    ) {
+        if !self.users.read().await.contains_key(&client_id) {
+            self.send_error(client_id, ErrorType::NotJoined);
+            return;
+        }
+
+        let room_name = create_room_request_data.name.trim();
+        if !self.room_name_regex.is_match(room_name) {
+            self.send_error(client_id, ErrorType::InvalidRoomName);
+            return;
+        }
+
+        let room_response = {
+            let mut rooms = self.rooms.write().await;
+            if rooms.values().any(|room| room.name == room_name) {
+                self.send_error(client_id, ErrorType::RoomExisted);
+                return;
+            }
+            let room = Room::new(Uuid::new_v4(), room_name);
+            let stored_room = StoredRoom::new(room.id, &room.name);
+            if let Err(err) = self.feed.write().await.add_room(stored_room) {
+                error!("Failed to store room {}: {}", room.id, err);
+                self.send_error(client_id, ErrorType::StorageFailed);
+                return;
+            }
+            let room_response = RoomResponse::from(&room);
+            rooms.insert(room.id, room);
+            room_response
+        };
+
+        self.send(ResponseData::RoomCreated(room_response)).await;
+    }
+
+    async fn process_list_rooms(&self, client_id: Uuid) {
+        if !self.users.read().await.contains_key(&client_id) {
+            self.send_error(client_id, ErrorType::NotJoined);
+            return;
+        }
+
+        let mut rooms: Vec<RoomResponse> = self
+            .rooms
+            .read()
+            .await
+            .values()
+            .map(RoomResponse::from)
+            .collect();
+        rooms.sort_by(|a, b| a.name.cmp(&b.name));
+
+        self.send_message_to_client(client_id, ResponseData::Rooms(RoomsResponse::new(rooms)));
+    }
+
+    async fn process_join_room(&self, client_id: Uuid, room_request_data: RoomRequestData) {
+        let users = self.users.read().await;
+        let user_response = if let Some(user) = users.get(&client_id) {
+            UserResponse::from(user)
+        } else {
+            self.send_error(client_id, ErrorType::NotJoined);
+            return;
+        };
+
+        let room_id = room_request_data.room_id;
+        let (room, members) = match self.rooms.write().await.get_mut(&room_id) {
+            Some(room) => {
+                room.members.insert(client_id);
+                (
+                    RoomResponse::from(&*room),
+                    Self::distinct_users(
+                        room.members
+                            .iter()
+                            .filter_map(|member_id| users.get(member_id)),
+                    ),
+                )
+            }
+            None => {
+                self.send_error(client_id, ErrorType::RoomNotFound);
+                return;
+            }
+        };
+        drop(users);
+        let messages = self.latest_room_messages(&room_id).await;
+        let room_joined = RoomJoinedResponse::new(room, members, messages);
+
+        self.send_message_to_client(client_id, ResponseData::RoomJoined(room_joined));
+
+        self.send_message_to_other_room_members(
+            room_id,
+            client_id,
+            ResponseData::UserJoinedRoom(UserJoinedRoomResponse::new(room_id, user_response)),
+        )
+        .await;
+    }
+
+    async fn process_leave_room(&self, client_id: Uuid, room_request_data: RoomRequestData) {
+        let user_id = if let Some(user) = self.users.read().await.get(&client_id) {
+            user.id
+        } else {
+            self.send_error(client_id, ErrorType::NotJoined);
+            return;
+        };
+
+        let room_id = room_request_data.room_id;
+        match self.rooms.write().await.get_mut(&room_id) {
+            Some(room) if room.is_member(&client_id) => {
+                room.members.remove(&client_id);
+            }
+            Some(_) => {
+                self.send_error(client_id, ErrorType::NotInRoom);
+                return;
+            }
+            None => {
+                self.send_error(client_id, ErrorType::RoomNotFound);
+                return;
+            }
+        }
+
+        self.send_message_to_client(
+            client_id,
+            ResponseData::RoomLeft(RoomLeftResponse::new(room_id)),
+        );
+
+        self.send_message_to_other_room_members(
+            room_id,
+            client_id,
+            ResponseData::UserLeftRoom(UserLeftRoomResponse::new(room_id, user_id)),
+        )
+        .await;
+    }
+
+    async fn process_direct_message(
+        &self,
+        client_id: Uuid,
+        direct_message_request_data: DirectMessageRequestData,
+    ) {
+        let (from, to) = {
+            let users = self.users.read().await;
+            let from = if let Some(user) = users.get(&client_id) {
+                user.clone()
+            } else {
+                self.send_error(client_id, ErrorType::NotJoined);
+                return;
+            };
+            let to_user_id = direct_message_request_data.to;
+            let to = if let Some(user) = users.values().find(|user| user.id == to_user_id) {
+                user.clone()
+            } else {
+                self.send_error(client_id, ErrorType::UserNotFound);
+                return;
+            };
+            (from, to)
+        };
+
+        if self.is_muted(&from.name).await {
+            self.send_error(client_id, ErrorType::Muted);
+            return;
+        }
+
+        if direct_message_request_data.text.is_empty() {
+            self.send_error(client_id, ErrorType::InvalidMessage);
+            return;
+        }
+
+        let message = DirectMessage::new(
+            Uuid::new_v4(),
+            from,
+            to,
+            &direct_message_request_data.text,
+            Utc::now(),
+        );
+        self.conversations
+            .write()
+            .await
+            .add_message(message.clone());
+
+        let message_response = DirectMessageResponse::from(&message);
+
+        self.send_message_to_client(
+            client_id,
+            ResponseData::DirectMessageSent(message_response.clone()),
+        );
+
+        self.send_message_to_user(
+            message.to.id,
+            client_id,
+            ResponseData::DirectMessageReceived(message_response),
+        )
+        .await;
+    }
+
+    async fn process_fetch_direct_messages(
+        &self,
+        client_id: Uuid,
+        fetch_direct_messages_request_data: FetchDirectMessagesRequestData,
+    ) {
+        let own_user_id = if let Some(user) = self.users.read().await.get(&client_id) {
+            user.id
+        } else {
+            self.send_error(client_id, ErrorType::NotJoined);
+            return;
+        };
+
+        let user_id = fetch_direct_messages_request_data.user_id;
+        let messages = self
+            .conversations
+            .read()
+            .await
+            .history(own_user_id, user_id)
+            .map(DirectMessageResponse::from)
+            .collect();
+
+        self.send_message_to_client(
+            client_id,
+            ResponseData::DirectMessages(DirectMessagesResponse::new(user_id, messages)),
+        );
+    }
+
+    async fn process_fetch_history(
+        &self,
+        client_id: Uuid,
+        fetch_history_request_data: FetchHistoryRequestData,
+    ) {
+        if !self.users.read().await.contains_key(&client_id) {
+            self.send_error(client_id, ErrorType::NotJoined);
+            return;
+        }
+
+        let room_id = fetch_history_request_data.room_id;
+        match self.rooms.read().await.get(&room_id) {
+            Some(room) if room.is_member(&client_id) => (),
+            Some(_) => {
+                self.send_error(client_id, ErrorType::NotInRoom);
+                return;
+            }
+            None => {
+                self.send_error(client_id, ErrorType::RoomNotFound);
+                return;
+            }
+        }
+
+        if fetch_history_request_data.limit == 0 {
+            self.send_error(
+                client_id,
+                ErrorType::invalid_request("limit must be at least 1"),
+            );
+            return;
+        }
+        let limit = fetch_history_request_data
+            .limit
+            .min(self.max_history_page_size);
+        let history = {
+            let store = self.feed.read().await;
+            let empty = Feed::default();
+            let feed = store.feed(&room_id).unwrap_or(&empty);
+            feed.page(fetch_history_request_data.before.as_ref(), limit)
+                .map(|(page, has_more)| {
+                    HistoryResponse::new(
+                        room_id,
+                        page.iter().map(MessageResponse::from).collect(),
+                        has_more,
+                    )
+                })
+        };
+
+        match history {
+            Some(history) => self.send_message_to_client(client_id, ResponseData::History(history)),
+            None => self.send_error(client_id, ErrorType::MessageNotFound),
+        }
+    }
+
+    async fn process_edit_message(
+        &self,
+        client_id: Uuid,
+        edit_message_request_data: EditMessageRequestData,
+    ) {
+        let (user_id, user_name) = if let Some(user) = self.users.read().await.get(&client_id) {
+            (user.id, user.name.clone())
+        } else {
+            self.send_error(client_id, ErrorType::NotJoined);
+            return;
+        };
+
+        // Rewriting old messages would be posting by other means
+        if self.is_muted(&user_name).await {
+            self.send_error(client_id, ErrorType::Muted);
+            return;
+        }
+
+        if edit_message_request_data.text.is_empty() {
+            self.send_error(client_id, ErrorType::InvalidMessage);
+            return;
+        }
+
+        let message_id = edit_message_request_data.id;
+        let message = {
+            let mut store = self.feed.write().await;
+            if let Err(error_type) = Self::check_author(store.message(&message_id), user_id) {
+                self.send_error(client_id, error_type);
+                return;
+            }
+            let text = &edit_message_request_data.text;
+            if let Err(err) = store.edit_message(&message_id, text, Utc::now()) {
+                error!("Failed to edit message {}: {}", message_id, err);
+                self.send_error(client_id, ErrorType::StorageFailed);
+                return;
+            }
+            store.message(&message_id).unwrap().clone()
+        };
+
+        let message_response = MessageResponse::from(&message);
+
+        self.send_message_to_client(
+            client_id,
+            ResponseData::MessageEdited(PostedResponse::new(message_response.clone())),
+        );
+
+        self.send_message_to_other_room_members(
+            message.room_id,
+            client_id,
+            ResponseData::MessageEdited(PostedResponse::new(message_response)),
+        )
+        .await;
+    }
+
+    async fn process_delete_message(
+        &self,
+        client_id: Uuid,
+        delete_message_request_data: DeleteMessageRequestData,
+    ) {
+        let user_id = if let Some(user) = self.users.read().await.get(&client_id) {
+            user.id
+        } else {
+            self.send_error(client_id, ErrorType::NotJoined);
+            return;
+        };
+
+        let message_id = delete_message_request_data.id;
+        let room_id = {
+            let mut store = self.feed.write().await;
+            let room_id = match Self::check_author(store.message(&message_id), user_id) {
+                Ok(message) => message.room_id,
+                Err(error_type) => {
+                    self.send_error(client_id, error_type);
+                    return;
+                }
+            };
+            if let Err(err) = store.delete_message(&message_id) {
+                error!("Failed to delete message {}: {}", message_id, err);
+                self.send_error(client_id, ErrorType::StorageFailed);
+                return;
+            }
+            room_id
+        };
+
+        let message_deleted = MessageDeletedResponse::new(room_id, message_id);
+
+        self.send_message_to_client(client_id, ResponseData::MessageDeleted(message_deleted));
+
+        self.send_message_to_other_room_members(
+            room_id,
+            client_id,
+            ResponseData::MessageDeleted(message_deleted),
+        )
+        .await;
+    }
+
+    async fn process_reaction(
+        &self,
+        client_id: Uuid,
+        reaction_request_data: ReactionRequestData,
+        added: bool,
+    ) {
+        let user_id = if let Some(user) = self.users.read().await.get(&client_id) {
+            user.id
+        } else {
+            self.send_error(client_id, ErrorType::NotJoined);
+            return;
+        };
+
+        let emoji = reaction_request_data.emoji.as_str();
+        if emoji.is_empty()
+            || emoji.chars().count() > MAX_REACTION_LENGTH
+            || emoji.chars().any(char::is_whitespace)
+        {
+            self.send_error(client_id, ErrorType::InvalidReaction);
+            return;
+        }
+
+        let message_id = reaction_request_data.message_id;
+        let room_id = match self.feed.read().await.message(&message_id) {
+            Some(message) if !message.deleted => message.room_id,
+            _ => {
+                self.send_error(client_id, ErrorType::MessageNotFound);
+                return;
+            }
+        };
+        match self.rooms.read().await.get(&room_id) {
+            Some(room) if room.is_member(&client_id) => (),
+            Some(_) => {
+                self.send_error(client_id, ErrorType::NotInRoom);
+                return;
+            }
+            None => {
+                self.send_error(client_id, ErrorType::RoomNotFound);
+                return;
+            }
+        }
+
+        // Reacting twice, or removing a reaction that is not there, is a no-op
+        {
+            let mut store = self.feed.write().await;
+            let message = store.message(&message_id).unwrap();
+            if message.has_reacted(emoji, &user_id) == added {
+                return;
+            }
+            let stored = if added {
+                store.react(&message_id, emoji, user_id)
+            } else {
+                store.unreact(&message_id, emoji, user_id)
+            };
+            if let Err(err) = stored {
+                error!("Failed to store reaction to {}: {}", message_id, err);
+                self.send_error(client_id, ErrorType::StorageFailed);
+                return;
+            }
+        }
+
+        let reaction = ReactionResponse::new(room_id, message_id, user_id, emoji);
+        let response_data = if added {
+            ResponseData::ReactionAdded(reaction)
+        } else {
+            ResponseData::ReactionRemoved(reaction)
+        };
+
+        self.send_message_to_client(client_id, response_data.clone());
+
+        self.send_message_to_other_room_members(room_id, client_id, response_data)
+            .await;
+    }
+
+    async fn process_mute(&self, client_id: Uuid, mute_request_data: MuteRequestData) {
+        if let Err(error_type) = self.check_moderator(client_id).await {
+            self.send_error(client_id, error_type);
+            return;
+        }
+
+        let (name, user_id) = match mute_request_data {
+            MuteRequestData {
+                user_id: Some(user_id),
+                name: None,
+                ..
+            } => {
+                let name = self
+                    .users
+                    .read()
+                    .await
+                    .values()
+                    .find(|user| user.id == user_id)
+                    .map(|user| user.name.clone());
+                match name {
+                    Some(name) => (name, Some(user_id)),
+                    None => {
+                        self.send_error(client_id, ErrorType::UserNotFound);
+                        return;
+                    }
+                }
+            }
+            MuteRequestData {
+                user_id: None,
+                name: Some(name),
+                ..
+            } => match name.trim() {
+                "" => {
+                    self.send_error(client_id, ErrorType::InvalidName);
+                    return;
+                }
+                name => (String::from(name), None),
+            },
+            _ => {
+                self.send_error(
+                    client_id,
+                    ErrorType::invalid_request("exactly one of userId and name must be set"),
+                );
+                return;
+            }
+        };
+
+        let until_utc = match mute_request_data.duration_secs {
+            0 => {
+                self.mutes.write().await.remove(&name);
+                None
+            }
+            duration_secs => {
+                let duration = Duration::from_secs(duration_secs);
+                let until = Instant::now().checked_add(duration);
+                let until_utc = ChronoDuration::from_std(duration)
+                    .ok()
+                    .and_then(|duration| Utc::now().checked_add_signed(duration));
+                match (until, until_utc) {
+                    (Some(until), Some(until_utc)) => {
+                        self.mutes.write().await.insert(name.clone(), until);
+                        Some(until_utc)
+                    }
+                    _ => {
+                        self.send_error(
+                            client_id,
+                            ErrorType::invalid_request("durationSecs is too long"),
+                        );
+                        return;
+                    }
+                }
+            }
+        };
+
+        let response_data =
+            ResponseData::UserMuted(UserMutedResponse::new(&name, user_id, until_utc));
+        self.send_message_to_client(client_id, response_data.clone());
+        let muted_user_ids: HashSet<Uuid> = self
+            .users
+            .read()
+            .await
+            .values()
+            .filter(|user| user.name == name)
+            .map(|user| user.id)
+            .collect();
+        for muted_user_id in muted_user_ids {
+            self.send_message_to_user(muted_user_id, client_id, response_data.clone())
+                .await;
+        }
+    }
+
+    async fn process_kick(&self, client_id: Uuid, kick_request_data: KickRequestData) {
+        if let Err(error_type) = self.check_moderator(client_id).await {
+            self.send_error(client_id, error_type);
+            return;
+        }
+
+        let user_id = kick_request_data.user_id;
+        if self.kick(user_id).await == 0 {
+            self.send_error(client_id, ErrorType::UserNotFound);
+            return;
+        }
+        self.send_message_to_client(
+            client_id,
+            ResponseData::UserKicked(UserLeftResponse::new(user_id)),
+        );
+    }
+
+    async fn process_ban(&self, client_id: Uuid, ban_request_data: BanRequestData) {
+        if let Err(error_type) = self.check_moderator(client_id).await {
+            self.send_error(client_id, error_type);
+            return;
+        }
+
+        let ban = match ban_request_data {
+            BanRequestData {
+                user_id: Some(user_id),
+                name: None,
+                ip: None,
+            } => {
+                let name = self
+                    .users
+                    .read()
+                    .await
+                    .values()
+                    .find(|user| user.id == user_id)
+                    .map(|user| user.name.clone());
+                match name {
+                    Some(name) => Ban::Name(name),
+                    None => {
+                        self.send_error(client_id, ErrorType::UserNotFound);
+                        return;
+                    }
+                }
+            }
+            BanRequestData {
+                user_id: None,
+                name: Some(name),
+                ip: None,
+            } => match name.trim() {
+                "" => {
+                    self.send_error(client_id, ErrorType::InvalidName);
+                    return;
+                }
+                name => Ban::Name(String::from(name)),
+            },
+            BanRequestData {
+                user_id: None,
+                name: None,
+                ip: Some(ip),
+            } => Ban::Address(ip),
+            _ => {
+                self.send_error(
+                    client_id,
+                    ErrorType::invalid_request("exactly one of userId, name and ip must be set"),
+                );
+                return;
+            }
+        };
+
+        if let Err(err) = self.bans.write().await.add(ban.clone()) {
+            error!("Failed to store ban {:?}: {}", ban, err);
+            self.send_error(client_id, ErrorType::StorageFailed);
+            return;
+        }
+
+        // Whoever the ban covers is sent away now rather than on their next visit
+        match &ban {
+            Ban::Name(name) => {
+                let user_ids: HashSet<Uuid> = self
+                    .users
+                    .read()
+                    .await
+                    .values()
+                    .filter(|user| user.name == *name)
+                    .map(|user| user.id)
+                    .collect();
+                for user_id in user_ids {
+                    self.kick(user_id).await;
+                }
+            }
+            Ban::Address(address) => {
+                let client_ids: Vec<Uuid> = self
+                    .addresses
+                    .read()
+                    .await
+                    .iter()
+                    .filter(|(_, client_address)| *client_address == address)
+                    .map(|(client_id, _)| *client_id)
+                    .collect();
+                let users: Vec<(Uuid, Uuid)> = {
+                    let users = self.users.read().await;
+                    client_ids
+                        .iter()
+                        .filter_map(|id| users.get(id).map(|user| (user.id, *id)))
+                        .collect()
+                };
+                {
+                    let mut sessions = self.sessions.write().await;
+                    for (user_id, client_id) in &users {
+                        sessions.revoke(user_id, &[*client_id]);
+                    }
+                    sessions.revoke_address(address);
+                }
+                self.close_clients(&client_ids, KICKED_CLOSE_CODE, "kicked")
+                    .await;
+            }
+        }
+
+        self.send_message_to_client(
+            client_id,
+            ResponseData::UserBanned(UserBannedResponse::new(ban)),
+        );
+    }
+
+    async fn check_moderator(&self, client_id: Uuid) -> std::result::Result<(), ErrorType> {
+        match self.users.read().await.get(&client_id) {
+            Some(user) if user.role == Role::Moderator => Ok(()),
+            Some(_) => Err(ErrorType::NotModerator),
+            None => Err(ErrorType::NotJoined),
+        }
+    }
+
+    /// Whether `name` is muted, forgetting the mute once it has run out.
+    async fn is_muted(&self, name: &str) -> bool {
+        let mut mutes = self.mutes.write().await;
+        match mutes.get(name) {
+            Some(until) if *until > Instant::now() => true,
+            Some(_) => {
+                mutes.remove(name);
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn check_author(
+        message: Option<&Message>,
+        user_id: Uuid,
+    ) -> std::result::Result<&Message, ErrorType> {
+        match message {
+            Some(message) if message.deleted => Err(ErrorType::MessageNotFound),
+            Some(message) if message.user.id != user_id => Err(ErrorType::NotAuthor),
+            Some(message) => Ok(message),
+            None => Err(ErrorType::MessageNotFound),
+        }
+    }
+
+    async fn is_online(&self, user_id: &Uuid) -> bool {
+        self.users
+            .read()
+            .await
+            .values()
+            .any(|user| user.id == *user_id)
+            || self.sessions.read().await.is_suspended(user_id)
+    }
+
+    /// One entry per user, however many sessions they have open.
+    fn distinct_users<'a>(users: impl Iterator<Item = &'a User>) -> Vec<UserResponse> {
+        let mut seen = HashSet::new();
+        users
+            .filter(|user| seen.insert(user.id))
+            .map(UserResponse::from)
+            .collect()
+    }
+
+    /// Users named by `@name` tokens in `text`, other than the author. Names
+    /// may contain spaces, so the longest name following an `@` wins.
+    fn mentioned_user_ids<'a>(
+        text: &str,
+        users: impl Iterator<Item = &'a User>,
+        author_id: Uuid,
+    ) -> Vec<Uuid> {
+        let users: Vec<&User> = users.filter(|user| user.id != author_id).collect();
+        let is_name_char = |c: char| c.is_alphanumeric() || c == '_';
+        let mut mentions = Vec::new();
+        for (at, _) in text.match_indices('@') {
+            if text[..at].chars().next_back().is_some_and(is_name_char) {
+                continue;
+            }
+            let rest = &text[at + 1..];
+            let mentioned = users
+                .iter()
+                .filter(|user| {
+                    let name = user.name.as_str();
+                    rest.get(..name.len())
+                        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(name))
+                        && !rest[name.len()..].chars().next().is_some_and(is_name_char)
+                })
+                .max_by_key(|user| user.name.len());
+            if let Some(user) = mentioned {
+                if !mentions.contains(&user.id) {
+                    mentions.push(user.id);
+                }
+            }
+        }
+        mentions
+    }
+
+    async fn latest_room_messages(&self, room_id: &Uuid) -> Vec<MessageResponse> {
+        self.feed
+            .read()
+            .await
+            .feed(room_id)
+            .map(|feed| {
+                feed.latest(self.history_page_size)
+                    .iter()
+                    .map(MessageResponse::from)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Messages posted in `room_ids` after `last_seen_message_id`, capped per
+    /// room like on join. Edits made in the meantime are not replayed.
+    async fn missed_messages(
+        &self,
+        room_ids: &[Uuid],
+        last_seen_message_id: Option<Uuid>,
+    ) -> (Vec<MessageResponse>, Vec<Uuid>) {
+        let store = self.feed.read().await;
+        // Messages from the same instant are cut by id, as feeds order them
+        let last_seen = last_seen_message_id
+            .and_then(|id| store.message(&id))
+            .map(|message| (message.created_at_utc, message.id));
+        let mut messages: Vec<&Message> = Vec::new();
+        let mut truncated_room_ids = Vec::new();
+        for room_id in room_ids {
+            let feed = match store.feed(room_id) {
+                Some(feed) => feed,
+                None => continue,
+            };
+            let missed = match last_seen {
+                Some((created_at_utc, id)) => feed.since(created_at_utc, id),
+                None => feed.latest(usize::MAX),
+            };
+            let start = missed.len().saturating_sub(self.history_page_size);
+            if start > 0 {
+                truncated_room_ids.push(*room_id);
+            }
+            messages.extend(&missed[start..]);
+        }
+        messages.sort_by_key(|message| (message.created_at_utc, message.id));
+        let messages = messages.into_iter().map(MessageResponse::from).collect();
+        (messages, truncated_room_ids)
+    }
+
+    async fn send(&self, response_data: ResponseData) {
+        self.deliver(self.users.read().await.keys(), response_data);
+    }
+
+    fn send_message_to_client(&self, client_id: Uuid, response_data: ResponseData) {
+        self.deliver(&[client_id], response_data);
+    }
+
+    async fn send_message_to_other_clients(&self, client_id: Uuid, response_data: ResponseData) {
+        self.deliver(
+            self.users
+                .read()
+                .await
+                .keys()
+                .filter(|other_client_id| **other_client_id != client_id),
+            response_data,
+        );
+    }
+
+    /// Sends to every session of `user_id` except `client_id`.
+    async fn send_message_to_user(
+        &self,
+        user_id: Uuid,
+        client_id: Uuid,
+        response_data: ResponseData,
+    ) {
+        self.deliver(
+            self.users
+                .read()
+                .await
+                .iter()
+                .filter(|(session_id, user)| user.id == user_id && **session_id != client_id)
+                .map(|(session_id, _)| session_id),
+            response_data,
+        );
+    }
+
+    async fn send_message_to_other_room_members(
+        &self,
+        room_id: Uuid,
+        client_id: Uuid,
+        response_data: ResponseData,
+    ) {
+        if let Some(room) = self.rooms.read().await.get(&room_id) {
+            self.deliver(
+                room.members
+                    .iter()
+                    .filter(|member_id| **member_id != client_id),
+                response_data,
+            );
+        }
+    }
+
+    /// Sends `response_data` to `client_id` with the id of the request being
+    /// processed, which is the client's own.
+    fn reply(&self, client_id: Uuid, response_data: ResponseData) {
+        let request_id = REQUEST_ID.try_with(Clone::clone).ok().flatten();
+        self.deliver(
+            &[client_id],
+            ResponseMessage::new(response_data, request_id),
+        );
+    }
+
+    /// Queues `response` for each of `client_ids` still connected.
+    fn deliver<'a>(
+        &self,
+        client_ids: impl IntoIterator<Item = &'a Uuid>,
+        response: impl Into<ResponseMessage>,
+    ) {
+        let response_message = response.into();
+        let outboxes = self.outboxes.read().unwrap();
+        for client_id in client_ids {
+            if let Some(outbox) = outboxes.get(client_id) {
+                let delivery = outbox.send(response_message.clone());
+                match delivery {
+                    Delivery::DroppedOldest => {
+                        warn!("Dropped oldest response queued for client {}", client_id)
+                    }
+                    Delivery::Coalesced => debug!("Coalesced Alive tick for client {}", client_id),
+                    Delivery::Disconnected => {
+                        warn!("Disconnecting client {} for falling behind", client_id)
+                    }
+                    // A closed queue belongs to a client that is disconnecting
+                    Delivery::Queued | Delivery::Closed => {}
+                }
+                self.overflow_counters.count(&delivery);
+            }
+        }
+    }
+
+    fn send_error(&self, client_id: Uuid, error_type: ErrorType) {
+        self.reply(client_id, ResponseData::Error(error_type))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{collections::HashMap, env, fs, time::Duration};
+
+    use chrono::Utc;
+    use futures::StreamExt;
+    use tokio::{runtime::Runtime, sync::mpsc};
+    use uuid::Uuid;
+
+    use crate::protocol::{
+        request::{
+            BanRequestData, CreateRoomRequestData, CredentialsRequestData,
+            DeleteMessageRequestData, DirectMessageRequestData, EditMessageRequestData,
+            FetchThreadRequestData, JoinRequestData, MarkReadRequestData, MuteRequestData,
+            PostMessageRequestData, ReactionRequestData, RequestData, RequestEnvelope,
+            RequestMessage, ResumeRequestData, RoomRequestData, SearchRequestData,
+        },
+        response::{
+            ErrorType, ReactionResponse, ReadReceiptResponse, ResponseData, ResponseMessage,
+        },
+    };
+    use crate::{
+        auth::{password::hash_password, Identity},
+        client::Client,
+        config::ServerConfig,
+        model::{
+            account::Account,
+            ban::Ban,
+            user::{Role, User},
+        },
+        outbox::{Inbox, Outbound, RATE_LIMITED_CLOSE_CODE},
+        store::{file::JsonLinesFeedStore, memory::MemoryFeedStore},
+    };
+
+    use super::{Worker, DEFAULT_ROOM_NAME};
+
+    #[test]
+    fn join_and_post() {
+        let worker = Worker::new(Some(Duration::from_secs(1)));
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async move {
+            let case = async {
+                let client_id = Uuid::new_v4();
+                let mut inboxes = connect(&worker, &[client_id]).await;
+
+                // Join
+                sender
+                    .send(RequestMessage::new(
+                        client_id,
+                        RequestData::Join(JoinRequestData {
+                            name: String::from("daolavi"),
+                        }),
+                    ))
+                    .unwrap();
+                let output = next_for(&mut inboxes, client_id).await;
+                println!("{:?}", output);
+                let user;
+                let room_id;
+                if let ResponseData::Joined(joined) = output {
+                    assert_eq!(joined.user.name.as_str(), "daolavi");
+                    assert_eq!(joined.room.name.as_str(), DEFAULT_ROOM_NAME);
+                    user = joined.user;
+                    room_id = joined.room.id;
+                } else {
+                    panic!("Expected Output::Joined got {:?}", output);
+                }
+
+                // Post message
+                sender
+                    .send(RequestMessage::new(
+                        client_id,
+                        RequestData::PostMessage(PostMessageRequestData {
+                            room_id,
+                            text: String::from("Hello"),
+                            reply_to: None,
+                        }),
+                    ))
+                    .unwrap();
+                let output = next_for(&mut inboxes, client_id).await;
+                if let ResponseData::Posted(posted) = output {
+                    assert_eq!(posted.message.text, "Hello");
+                    assert_eq!(posted.message.room_id, room_id);
+                    assert_eq!(posted.message.user.id, user.id);
+                    assert_eq!(posted.message.user.name, user.name);
+                } else {
+                    panic!("Expected Output::Posted got {:?}", output);
+                }
+            };
+            tokio::select! {
+              _ = worker.run(receiver) => {},
+              _ = case => {},
+            }
+        });
+    }
+
+    type Inboxes = HashMap<Uuid, Inbox>;
+
+    async fn connect(worker: &Worker, client_ids: &[Uuid]) -> Inboxes {
+        let mut inboxes = HashMap::new();
+        for client_id in client_ids {
+            let mut client = Client::new();
+            client.id = *client_id;
+            inboxes.insert(*client_id, worker.on_connect(&client).await);
+        }
+        inboxes
+    }
+
+    async fn next_for(inboxes: &mut Inboxes, client_id: Uuid) -> ResponseData {
+        inboxes.get_mut(&client_id).unwrap().recv().await.unwrap()
+    }
+
+    /// The next typing change `client_id` hears of, past ticks and join notices.
+    async fn next_typing(inboxes: &mut Inboxes, client_id: Uuid) -> (Uuid, bool) {
+        loop {
+            match next_for(inboxes, client_id).await {
+                ResponseData::UserTyping(typing) => return (typing.user_id, typing.typing),
+                ResponseData::Alive | ResponseData::Joined(_) | ResponseData::UserJoined(_) => {
+                    continue
+                }
+                output => panic!("Expected Output::UserTyping got {:?}", output),
+            }
+        }
+    }
+
+    async fn next_receipt(inboxes: &mut Inboxes, client_id: Uuid) -> ReadReceiptResponse {
+        match next_for(inboxes, client_id).await {
+            ResponseData::ReadReceipt(receipt) => receipt,
+            output => panic!("Expected Output::ReadReceipt got {:?}", output),
+        }
+    }
+
+    #[test]
+    fn room_posts_reach_only_members() {
+        let worker = Worker::new(None);
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async move {
+            let case = async {
+                let alice = Uuid::new_v4();
+                let bob = Uuid::new_v4();
+                let mut inboxes = connect(&worker, &[alice, bob]).await;
+
+                for (client_id, name) in [(alice, "alice"), (bob, "bobby")].iter() {
+                    sender
+                        .send(RequestMessage::new(
+                            *client_id,
+                            RequestData::Join(JoinRequestData {
+                                name: String::from(*name),
+                            }),
+                        ))
+                        .unwrap();
+                }
+                let general_id = match next_for(&mut inboxes, alice).await {
+                    ResponseData::Joined(joined) => joined.room.id,
+                    output => panic!("Expected Output::Joined got {:?}", output),
+                };
+
+                // Create and join a room
+                sender
+                    .send(RequestMessage::new(
+                        alice,
+                        RequestData::CreateRoom(CreateRoomRequestData {
+                            name: String::from("team"),
+                        }),
+                    ))
+                    .unwrap();
+                let output = next_for(&mut inboxes, alice).await;
+                let room_id = match output {
+                    ResponseData::UserJoined(_) => match next_for(&mut inboxes, alice).await {
+                        ResponseData::RoomCreated(room) => room.id,
+                        output => panic!("Expected Output::RoomCreated got {:?}", output),
+                    },
+                    output => panic!("Expected Output::UserJoined got {:?}", output),
+                };
+                sender
+                    .send(RequestMessage::new(
+                        alice,
+                        RequestData::JoinRoom(RoomRequestData { room_id }),
+                    ))
+                    .unwrap();
+                let output = next_for(&mut inboxes, alice).await;
+                if let ResponseData::RoomJoined(room_joined) = output {
+                    assert_eq!(room_joined.room.name, "team");
+                    assert_eq!(room_joined.users.len(), 1);
+                } else {
+                    panic!("Expected Output::RoomJoined got {:?}", output);
+                }
+
+                // Post in the room, only alice is a member
+                sender
+                    .send(RequestMessage::new(
+                        alice,
+                        RequestData::PostMessage(PostMessageRequestData {
+                            room_id,
+                            text: String::from("Hello team"),
+                            reply_to: None,
+                        }),
+                    ))
+                    .unwrap();
+                sender
+                    .send(RequestMessage::new(
+                        bob,
+                        RequestData::PostMessage(PostMessageRequestData {
+                            room_id,
+                            text: String::from("Hello?"),
+                            reply_to: None,
+                        }),
+                    ))
+                    .unwrap();
+                let output = loop {
+                    match next_for(&mut inboxes, bob).await {
+                        ResponseData::Joined(_) | ResponseData::RoomCreated(_) => continue,
+                        output => break output,
+                    }
+                };
+                assert_eq!(output, ResponseData::Error(ErrorType::NotInRoom));
+
+                // Both are members of the default room
+                sender
+                    .send(RequestMessage::new(
+                        bob,
+                        RequestData::PostMessage(PostMessageRequestData {
+                            room_id: general_id,
+                            text: String::from("Hello general"),
+                            reply_to: None,
+                        }),
+                    ))
+                    .unwrap();
+                let output = loop {
+                    match next_for(&mut inboxes, alice).await {
+                        ResponseData::Posted(_) => continue,
+                        output => break output,
+                    }
+                };
+                if let ResponseData::UserPosted(posted) = output {
+                    assert_eq!(posted.message.text, "Hello general");
+                    assert_eq!(posted.message.room_id, general_id);
+                } else {
+                    panic!("Expected Output::UserPosted got {:?}", output);
+                }
+            };
+            tokio::select! {
+              _ = worker.run(receiver) => {},
+              _ = case => {},
+            }
+        });
+    }
+
+    #[test]
+    fn direct_message_reaches_only_recipient() {
+        let worker = Worker::new(None);
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async move {
+            let case = async {
+                let alice = Uuid::new_v4();
+                let bob = Uuid::new_v4();
+                let carol = Uuid::new_v4();
+                let mut inboxes = connect(&worker, &[alice, bob, carol]).await;
+
+                let users = [(alice, "alice"), (bob, "bobby"), (carol, "carol")];
+                for (client_id, name) in users.iter() {
+                    sender
+                        .send(RequestMessage::new(
+                            *client_id,
+                            RequestData::Join(JoinRequestData {
+                                name: String::from(*name),
+                            }),
+                        ))
+                        .unwrap();
+                }
+                sender
+                    .send(RequestMessage::new(
+                        alice,
+                        RequestData::DirectMessage(DirectMessageRequestData {
+                            to: bob,
+                            text: String::from("Psst"),
+                        }),
+                    ))
+                    .unwrap();
+
+                let output = loop {
+                    match next_for(&mut inboxes, alice).await {
+                        ResponseData::Joined(_) | ResponseData::UserJoined(_) => continue,
+                        output => break output,
+                    }
+                };
+                if let ResponseData::DirectMessageSent(sent) = output {
+                    assert_eq!(sent.to.id, bob);
+                    assert_eq!(sent.text, "Psst");
+                } else {
+                    panic!("Expected Output::DirectMessageSent got {:?}", output);
+                }
+
+                let output = loop {
+                    match next_for(&mut inboxes, bob).await {
+                        ResponseData::Joined(_) | ResponseData::UserJoined(_) => continue,
+                        output => break output,
+                    }
+                };
+                if let ResponseData::DirectMessageReceived(received) = output {
+                    assert_eq!(received.from.id, alice);
+                } else {
+                    panic!("Expected Output::DirectMessageReceived got {:?}", output);
+                }
+
+                // Carol only ever heard about people joining
+                let carol_inbox = inboxes.get_mut(&carol).unwrap();
+                while let Some(output) = carol_inbox.try_recv() {
+                    match output {
+                        ResponseData::Joined(_) | ResponseData::UserJoined(_) => {}
+                        output => panic!("Expected nothing for carol got {:?}", output),
+                    }
+                }
+
+                assert_eq!(
+                    worker
+                        .conversations
+                        .read()
+                        .await
+                        .history(bob, alice)
+                        .count(),
+                    1
+                );
+            };
+            tokio::select! {
+              _ = worker.run(receiver) => {},
+              _ = case => {},
+            }
+        });
+    }
+
+    #[test]
+    fn joined_replays_stored_feed() {
+        let path = env::temp_dir().join(format!("feed-{}.jsonl", Uuid::new_v4()));
+        let rt = Runtime::new().unwrap();
+
+        for text in ["Hello", "Again"].iter() {
+            let store = Box::new(JsonLinesFeedStore::open(&path).unwrap());
+            let worker = Worker::with_store(None, store).unwrap();
+            let (sender, receiver) = mpsc::unbounded_channel();
+
+            let output = rt.block_on(async {
+                let case = async {
+                    let client_id = Uuid::new_v4();
+                    let mut inboxes = connect(&worker, &[client_id]).await;
+                    sender
+                        .send(RequestMessage::new(
+                            client_id,
+                            RequestData::Join(JoinRequestData {
+                                name: String::from("daolavi"),
+                            }),
+                        ))
+                        .unwrap();
+                    let output = next_for(&mut inboxes, client_id).await;
+                    if let ResponseData::Joined(joined) = &output {
+                        sender
+                            .send(RequestMessage::new(
+                                client_id,
+                                RequestData::PostMessage(PostMessageRequestData {
+                                    room_id: joined.room.id,
+                                    text: String::from(*text),
+                                    reply_to: None,
+                                }),
+                            ))
+                            .unwrap();
+                        next_for(&mut inboxes, client_id).await;
+                    }
+                    output
+                };
+                tokio::select! {
+                  _ = worker.run(receiver) => unreachable!(),
+                  output = case => output,
+                }
+            });
+
+            if let ResponseData::Joined(joined) = output {
+                let texts: Vec<&str> = joined.messages.iter().map(|m| m.text.as_str()).collect();
+                match *text {
+                    "Hello" => assert!(texts.is_empty()),
+                    _ => assert_eq!(texts, vec!["Hello"]),
+                }
+            } else {
+                panic!("Expected Output::Joined got {:?}", output);
+            }
+        }
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn only_author_edits_and_deletes() {
+        let worker = Worker::new(None);
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async move {
+            let case = async {
+                let alice = Uuid::new_v4();
+                let bob = Uuid::new_v4();
+                let mut inboxes = connect(&worker, &[alice, bob]).await;
+                let send = |client_id, request_data| {
+                    sender
+                        .send(RequestMessage::new(client_id, request_data))
+                        .unwrap()
+                };
+
+                for (client_id, name) in [(alice, "alice"), (bob, "bobby")].iter() {
+                    send(
+                        *client_id,
+                        RequestData::Join(JoinRequestData {
+                            name: String::from(*name),
+                        }),
+                    );
+                }
+                let room_id = match next_for(&mut inboxes, alice).await {
+                    ResponseData::Joined(joined) => joined.room.id,
+                    output => panic!("Expected Output::Joined got {:?}", output),
+                };
+                send(
+                    alice,
+                    RequestData::PostMessage(PostMessageRequestData {
+                        room_id,
+                        text: String::from("Helo"),
+                        reply_to: None,
+                    }),
+                );
+                let id = loop {
+                    if let ResponseData::Posted(posted) = next_for(&mut inboxes, alice).await {
+                        break posted.message.id;
+                    }
+                };
+
+                send(
+                    alice,
+                    RequestData::EditMessage(EditMessageRequestData {
+                        id,
+                        text: String::from("Hello"),
+                    }),
+                );
+                let output = loop {
+                    match next_for(&mut inboxes, bob).await {
+                        ResponseData::Joined(_) | ResponseData::UserPosted(_) => continue,
+                        output => break output,
+                    }
+                };
+                if let ResponseData::MessageEdited(edited) = output {
+                    assert_eq!(edited.message.text, "Hello");
+                    assert!(edited.message.edited_at_utc.is_some());
+                } else {
+                    panic!("Expected Output::MessageEdited got {:?}", output);
+                }
+
+                send(
+                    bob,
+                    RequestData::DeleteMessage(DeleteMessageRequestData { id }),
+                );
+                let output = next_for(&mut inboxes, bob).await;
+                assert_eq!(output, ResponseData::Error(ErrorType::NotAuthor));
+
+                send(
+                    alice,
+                    RequestData::DeleteMessage(DeleteMessageRequestData { id }),
+                );
+                let output = next_for(&mut inboxes, bob).await;
+                if let ResponseData::MessageDeleted(deleted) = output {
+                    assert_eq!(deleted.message_id, id);
+                } else {
+                    panic!("Expected Output::MessageDeleted got {:?}", output);
+                }
+                let store = worker.feed.read().await;
+                assert!(store.message(&id).unwrap().deleted);
+            };
+            tokio::select! {
+              _ = worker.run(receiver) => {},
+              _ = case => {},
+            }
+        });
     }
-}
 
-#[cfg(test)]
-mod tests {
+    #[test]
+    fn login_shares_account_identity_across_sessions() {
+        let worker = Worker::new(None);
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async move {
+            let case = async {
+                let laptop = Uuid::new_v4();
+                let phone = Uuid::new_v4();
+                let guest = Uuid::new_v4();
+                let mut inboxes = connect(&worker, &[laptop, phone, guest]).await;
+                let credentials = |password: &str| CredentialsRequestData {
+                    name: String::from("daolavi"),
+                    password: String::from(password),
+                };
+
+                sender
+                    .send(RequestMessage::new(
+                        laptop,
+                        RequestData::Register(credentials("correct horse")),
+                    ))
+                    .unwrap();
+                let account_id = match next_for(&mut inboxes, laptop).await {
+                    ResponseData::Joined(joined) => joined.user.id,
+                    output => panic!("Expected Output::Joined got {:?}", output),
+                };
+                assert_ne!(account_id, laptop);
+
+                sender
+                    .send(RequestMessage::new(
+                        phone,
+                        RequestData::Login(credentials("battery staple")),
+                    ))
+                    .unwrap();
+                let output = next_for(&mut inboxes, phone).await;
+                assert_eq!(output, ResponseData::Error(ErrorType::InvalidCredentials));
+
+                sender
+                    .send(RequestMessage::new(
+                        phone,
+                        RequestData::Login(credentials("correct horse")),
+                    ))
+                    .unwrap();
+                match next_for(&mut inboxes, phone).await {
+                    ResponseData::Joined(joined) => assert_eq!(joined.user.id, account_id),
+                    output => panic!("Expected Output::Joined got {:?}", output),
+                }
+
+                sender
+                    .send(RequestMessage::new(
+                        guest,
+                        RequestData::Join(JoinRequestData {
+                            name: String::from("daolavi"),
+                        }),
+                    ))
+                    .unwrap();
+                let output = next_for(&mut inboxes, guest).await;
+                assert_eq!(output, ResponseData::Error(ErrorType::NameExisted));
+
+                // Dropping one session keeps the account online
+                worker.on_disconnect(phone).await;
+                assert!(worker.is_online(&account_id).await);
+                assert_eq!(worker.users.read().await.len(), 1);
+            };
+            tokio::select! {
+              _ = worker.run(receiver) => {},
+              _ = case => {},
+            }
+        });
+    }
+
+    #[test]
+    fn hashing_passwords_does_not_hold_up_other_clients() {
+        let worker = Worker::new(None);
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async move {
+            let case = async {
+                let member = Uuid::new_v4();
+                let guest = Uuid::new_v4();
+                let mut inboxes = connect(&worker, &[member, guest]).await;
+
+                sender
+                    .send(RequestMessage::new(
+                        member,
+                        RequestData::Register(CredentialsRequestData {
+                            name: String::from("daolavi"),
+                            password: String::from("correct horse"),
+                        }),
+                    ))
+                    .unwrap();
+                sender
+                    .send(RequestMessage::new(
+                        guest,
+                        RequestData::Join(JoinRequestData {
+                            name: String::from("guest"),
+                        }),
+                    ))
+                    .unwrap();
+                assert!(matches!(
+                    next_for(&mut inboxes, guest).await,
+                    ResponseData::Joined(_)
+                ));
+                assert_eq!(inboxes.get_mut(&member).unwrap().try_recv(), None);
+
+                match next_for(&mut inboxes, member).await {
+                    ResponseData::Joined(joined) => assert_eq!(joined.user.name, "daolavi"),
+                    output => panic!("Expected Output::Joined got {:?}", output),
+                }
+            };
+            tokio::select! {
+              _ = worker.run(receiver) => {},
+              _ = case => {},
+            }
+        });
+    }
+
+    #[test]
+    fn resume_replays_missed_messages_without_churn() {
+        let worker = Worker::new(None);
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async move {
+            let case = async {
+                let alice = Uuid::new_v4();
+                let bob = Uuid::new_v4();
+                let bob_again = Uuid::new_v4();
+                let stranger = Uuid::new_v4();
+                let mut inboxes = connect(&worker, &[alice, bob, bob_again, stranger]).await;
+                let join = |name: &str| {
+                    RequestData::Join(JoinRequestData {
+                        name: String::from(name),
+                    })
+                };
+
+                sender
+                    .send(RequestMessage::new(alice, join("alice")))
+                    .unwrap();
+                let room_id = match next_for(&mut inboxes, alice).await {
+                    ResponseData::Joined(joined) => joined.room.id,
+                    output => panic!("Expected Output::Joined got {:?}", output),
+                };
+                sender
+                    .send(RequestMessage::new(bob, join("bobby")))
+                    .unwrap();
+                let (bob_id, token) = match next_for(&mut inboxes, bob).await {
+                    ResponseData::Joined(joined) => (joined.user.id, joined.resume_token),
+                    output => panic!("Expected Output::Joined got {:?}", output),
+                };
+                match next_for(&mut inboxes, alice).await {
+                    ResponseData::UserJoined(_) => {}
+                    output => panic!("Expected Output::UserJoined got {:?}", output),
+                }
+
+                let post = |text: &str| {
+                    RequestData::PostMessage(PostMessageRequestData {
+                        room_id,
+                        text: String::from(text),
+                        reply_to: None,
+                    })
+                };
+                sender
+                    .send(RequestMessage::new(alice, post("before")))
+                    .unwrap();
+                next_for(&mut inboxes, alice).await;
+                let last_seen_message_id = match next_for(&mut inboxes, bob).await {
+                    ResponseData::UserPosted(posted) => posted.message.id,
+                    output => panic!("Expected Output::UserPosted got {:?}", output),
+                };
+
+                worker.on_disconnect(bob).await;
+                sender
+                    .send(RequestMessage::new(alice, post("missed")))
+                    .unwrap();
+                next_for(&mut inboxes, alice).await;
+
+                sender
+                    .send(RequestMessage::new(
+                        bob_again,
+                        RequestData::Resume(ResumeRequestData {
+                            token: token.clone(),
+                            last_seen_message_id: Some(last_seen_message_id),
+                        }),
+                    ))
+                    .unwrap();
+                match next_for(&mut inboxes, bob_again).await {
+                    ResponseData::Resumed(resumed) => {
+                        assert_eq!(resumed.user.id, bob_id);
+                        assert_eq!(resumed.rooms.len(), 1);
+                        assert_eq!(resumed.other_users.len(), 1);
+                        let texts: Vec<&str> =
+                            resumed.messages.iter().map(|m| m.text.as_str()).collect();
+                        assert_eq!(texts, vec!["missed"]);
+                        assert!(resumed.truncated_room_ids.is_empty());
+                        assert_ne!(resumed.resume_token, token);
+                    }
+                    output => panic!("Expected Output::Resumed got {:?}", output),
+                }
+
+                // Alice never saw bob leave or come back
+                sender
+                    .send(RequestMessage::new(alice, post("after")))
+                    .unwrap();
+                match next_for(&mut inboxes, alice).await {
+                    ResponseData::Posted(posted) => assert_eq!(posted.message.text, "after"),
+                    output => panic!("Expected Output::Posted got {:?}", output),
+                }
+                match next_for(&mut inboxes, bob_again).await {
+                    ResponseData::UserPosted(posted) => assert_eq!(posted.message.text, "after"),
+                    output => panic!("Expected Output::UserPosted got {:?}", output),
+                }
+
+                // Tokens are single use
+                sender
+                    .send(RequestMessage::new(
+                        stranger,
+                        RequestData::Resume(ResumeRequestData {
+                            token,
+                            last_seen_message_id: None,
+                        }),
+                    ))
+                    .unwrap();
+                let output = next_for(&mut inboxes, stranger).await;
+                assert_eq!(output, ResponseData::Error(ErrorType::InvalidResumeToken));
+            };
+            tokio::select! {
+              _ = worker.run(receiver) => {},
+              _ = case => {},
+            }
+        });
+    }
+
+    #[test]
+    fn resume_flags_rooms_with_more_missed_than_a_page() {
+        let mut worker = Worker::new(None);
+        worker.history_page_size = 1;
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async move {
+            let case = async {
+                let alice = Uuid::new_v4();
+                let bob = Uuid::new_v4();
+                let bob_again = Uuid::new_v4();
+                let mut inboxes = connect(&worker, &[alice, bob, bob_again]).await;
+                let join = |name: &str| {
+                    RequestData::Join(JoinRequestData {
+                        name: String::from(name),
+                    })
+                };
+
+                sender
+                    .send(RequestMessage::new(alice, join("alice")))
+                    .unwrap();
+                let room_id = match next_for(&mut inboxes, alice).await {
+                    ResponseData::Joined(joined) => joined.room.id,
+                    output => panic!("Expected Output::Joined got {:?}", output),
+                };
+                sender
+                    .send(RequestMessage::new(bob, join("bobby")))
+                    .unwrap();
+                let token = match next_for(&mut inboxes, bob).await {
+                    ResponseData::Joined(joined) => joined.resume_token,
+                    output => panic!("Expected Output::Joined got {:?}", output),
+                };
+                next_for(&mut inboxes, alice).await;
+
+                worker.on_disconnect(bob).await;
+                for text in ["first", "second"] {
+                    sender
+                        .send(RequestMessage::new(
+                            alice,
+                            RequestData::PostMessage(PostMessageRequestData {
+                                room_id,
+                                text: String::from(text),
+                                reply_to: None,
+                            }),
+                        ))
+                        .unwrap();
+                    next_for(&mut inboxes, alice).await;
+                }
+
+                sender
+                    .send(RequestMessage::new(
+                        bob_again,
+                        RequestData::Resume(ResumeRequestData {
+                            token,
+                            last_seen_message_id: None,
+                        }),
+                    ))
+                    .unwrap();
+                match next_for(&mut inboxes, bob_again).await {
+                    ResponseData::Resumed(resumed) => {
+                        let texts: Vec<&str> =
+                            resumed.messages.iter().map(|m| m.text.as_str()).collect();
+                        assert_eq!(texts, vec!["second"]);
+                        assert_eq!(resumed.truncated_room_ids, vec![room_id]);
+                    }
+                    output => panic!("Expected Output::Resumed got {:?}", output),
+                }
+            };
+            tokio::select! {
+              _ = worker.run(receiver) => {},
+              _ = case => {},
+            }
+        });
+    }
+
+    #[test]
+    fn typing_is_relayed_and_expires() {
+        // Typing expires on its own timer, with or without `Alive` ticks
+        let mut worker = Worker::new(None);
+        worker.typing_timeout = Duration::from_millis(200);
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async move {
+            let case = async {
+                let alice = Uuid::new_v4();
+                let bob = Uuid::new_v4();
+                let mut inboxes = connect(&worker, &[alice, bob]).await;
+                for (client_id, name) in [(alice, "alice"), (bob, "bobby")].iter() {
+                    sender
+                        .send(RequestMessage::new(
+                            *client_id,
+                            RequestData::Join(JoinRequestData {
+                                name: String::from(*name),
+                            }),
+                        ))
+                        .unwrap();
+                }
+                let (alice_id, room_id) = loop {
+                    if let ResponseData::Joined(joined) = next_for(&mut inboxes, alice).await {
+                        break (joined.user.id, joined.room.id);
+                    }
+                };
+                let typing = |typing: bool| {
+                    let room = RoomRequestData { room_id };
+                    let request_data = if typing {
+                        RequestData::StartTyping(room)
+                    } else {
+                        RequestData::StopTyping(room)
+                    };
+                    sender
+                        .send(RequestMessage::new(alice, request_data))
+                        .unwrap();
+                };
+
+                typing(true);
+                assert_eq!(next_typing(&mut inboxes, bob).await, (alice_id, true));
+                typing(false);
+                assert_eq!(next_typing(&mut inboxes, bob).await, (alice_id, false));
+
+                // No StopTyping this time
+                let started = std::time::Instant::now();
+                typing(true);
+                assert_eq!(next_typing(&mut inboxes, bob).await, (alice_id, true));
+                assert_eq!(next_typing(&mut inboxes, bob).await, (alice_id, false));
+                let elapsed = started.elapsed();
+                assert!(elapsed >= worker.typing_timeout && elapsed < worker.typing_timeout * 2);
+                assert!(worker.typing.read().await.is_empty());
+            };
+            tokio::select! {
+              _ = worker.run(receiver) => {},
+              _ = case => {},
+            }
+        });
+    }
+
+    #[test]
+    fn read_pointers_only_move_forward() {
+        let worker = Worker::new(None);
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async move {
+            let case = async {
+                let alice = Uuid::new_v4();
+                let laptop = Uuid::new_v4();
+                let phone = Uuid::new_v4();
+                let mut inboxes = connect(&worker, &[alice, laptop, phone]).await;
+                let credentials = || CredentialsRequestData {
+                    name: String::from("daolavi"),
+                    password: String::from("correct horse"),
+                };
+
+                sender
+                    .send(RequestMessage::new(
+                        alice,
+                        RequestData::Join(JoinRequestData {
+                            name: String::from("alice"),
+                        }),
+                    ))
+                    .unwrap();
+                let room_id = match next_for(&mut inboxes, alice).await {
+                    ResponseData::Joined(joined) => joined.room.id,
+                    output => panic!("Expected Output::Joined got {:?}", output),
+                };
+                let mut message_ids = Vec::new();
+                for text in ["one", "two", "three"].iter() {
+                    sender
+                        .send(RequestMessage::new(
+                            alice,
+                            RequestData::PostMessage(PostMessageRequestData {
+                                room_id,
+                                text: String::from(*text),
+                                reply_to: None,
+                            }),
+                        ))
+                        .unwrap();
+                    match next_for(&mut inboxes, alice).await {
+                        ResponseData::Posted(posted) => message_ids.push(posted.message.id),
+                        output => panic!("Expected Output::Posted got {:?}", output),
+                    }
+                }
+
+                sender
+                    .send(RequestMessage::new(
+                        laptop,
+                        RequestData::Register(credentials()),
+                    ))
+                    .unwrap();
+                let account_id = match next_for(&mut inboxes, laptop).await {
+                    ResponseData::Joined(joined) => {
+                        assert_eq!(joined.unread_count, 3);
+                        joined.user.id
+                    }
+                    output => panic!("Expected Output::Joined got {:?}", output),
+                };
+                let mark_read = |client_id: Uuid, message_id: Uuid| {
+                    sender
+                        .send(RequestMessage::new(
+                            client_id,
+                            RequestData::MarkRead(MarkReadRequestData { message_id }),
+                        ))
+                        .unwrap();
+                };
+                assert!(matches!(
+                    next_for(&mut inboxes, alice).await,
+                    ResponseData::UserJoined(_)
+                ));
+                mark_read(laptop, message_ids[1]);
+                let receipt = next_receipt(&mut inboxes, alice).await;
+                assert_eq!(
+                    (receipt.room_id, receipt.user_id, receipt.message_id),
+                    (room_id, account_id, message_ids[1])
+                );
+
+                sender
+                    .send(RequestMessage::new(
+                        phone,
+                        RequestData::Login(credentials()),
+                    ))
+                    .unwrap();
+                match next_for(&mut inboxes, phone).await {
+                    ResponseData::Joined(joined) => assert_eq!(joined.unread_count, 1),
+                    output => panic!("Expected Output::Joined got {:?}", output),
+                }
+
+                // A stale session reporting an older message is ignored
+                mark_read(laptop, message_ids[0]);
+                mark_read(phone, message_ids[2]);
+                let receipt = next_receipt(&mut inboxes, alice).await;
+                assert_eq!(receipt.message_id, message_ids[2]);
+
+                mark_read(phone, Uuid::new_v4());
+                let output = next_for(&mut inboxes, phone).await;
+                assert_eq!(output, ResponseData::Error(ErrorType::MessageNotFound));
+            };
+            tokio::select! {
+              _ = worker.run(receiver) => {},
+              _ = case => {},
+            }
+        });
+    }
+
+    #[test]
+    fn reactions_are_relayed_and_replayed_on_join() {
+        let worker = Worker::new(None);
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async move {
+            let case = async {
+                let alice = Uuid::new_v4();
+                let bob = Uuid::new_v4();
+                let carol = Uuid::new_v4();
+                let mut inboxes = connect(&worker, &[alice, bob, carol]).await;
+                let join = |name: &str| {
+                    RequestData::Join(JoinRequestData {
+                        name: String::from(name),
+                    })
+                };
+
+                sender
+                    .send(RequestMessage::new(alice, join("alice")))
+                    .unwrap();
+                let room_id = match next_for(&mut inboxes, alice).await {
+                    ResponseData::Joined(joined) => joined.room.id,
+                    output => panic!("Expected Output::Joined got {:?}", output),
+                };
+                sender
+                    .send(RequestMessage::new(
+                        alice,
+                        RequestData::PostMessage(PostMessageRequestData {
+                            room_id,
+                            text: String::from("Ship it?"),
+                            reply_to: None,
+                        }),
+                    ))
+                    .unwrap();
+                let message_id = match next_for(&mut inboxes, alice).await {
+                    ResponseData::Posted(posted) => posted.message.id,
+                    output => panic!("Expected Output::Posted got {:?}", output),
+                };
+                sender
+                    .send(RequestMessage::new(bob, join("bobby")))
+                    .unwrap();
+                let bob_id = match next_for(&mut inboxes, bob).await {
+                    ResponseData::Joined(joined) => joined.user.id,
+                    output => panic!("Expected Output::Joined got {:?}", output),
+                };
+                assert!(matches!(
+                    next_for(&mut inboxes, alice).await,
+                    ResponseData::UserJoined(_)
+                ));
+
+                let reaction = |emoji: &str| ReactionRequestData {
+                    message_id,
+                    emoji: String::from(emoji),
+                };
+                for request_data in [
+                    RequestData::React(reaction("👍")),
+                    RequestData::React(reaction("👍")),
+                    RequestData::React(reaction("🎉")),
+                    RequestData::Unreact(reaction("🎉")),
+                    RequestData::React(reaction("no way")),
+                ] {
+                    sender.send(RequestMessage::new(bob, request_data)).unwrap();
+                }
+                // The repeated reaction is not relayed again
+                let expected =
+                    |emoji: &str| ReactionResponse::new(room_id, message_id, bob_id, emoji);
+                for client_id in [alice, bob].iter() {
+                    assert_eq!(
+                        next_for(&mut inboxes, *client_id).await,
+                        ResponseData::ReactionAdded(expected("👍"))
+                    );
+                    assert_eq!(
+                        next_for(&mut inboxes, *client_id).await,
+                        ResponseData::ReactionAdded(expected("🎉"))
+                    );
+                    assert_eq!(
+                        next_for(&mut inboxes, *client_id).await,
+                        ResponseData::ReactionRemoved(expected("🎉"))
+                    );
+                }
+                let output = next_for(&mut inboxes, bob).await;
+                assert_eq!(output, ResponseData::Error(ErrorType::InvalidReaction));
+
+                sender
+                    .send(RequestMessage::new(carol, join("carol")))
+                    .unwrap();
+                match next_for(&mut inboxes, carol).await {
+                    ResponseData::Joined(joined) => {
+                        let reactions = &joined.messages[0].reactions;
+                        assert_eq!(reactions.len(), 1);
+                        assert!(reactions["👍"].contains(&bob_id));
+                    }
+                    output => panic!("Expected Output::Joined got {:?}", output),
+                }
+            };
+            tokio::select! {
+              _ = worker.run(receiver) => {},
+              _ = case => {},
+            }
+        });
+    }
+
+    #[test]
+    fn replies_are_threaded_under_their_root() {
+        let worker = Worker::new(None);
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async move {
+            let case = async {
+                let alice = Uuid::new_v4();
+                let bob = Uuid::new_v4();
+                let mut inboxes = connect(&worker, &[alice, bob]).await;
+                for (client_id, name) in [(alice, "alice"), (bob, "bobby")].iter() {
+                    sender
+                        .send(RequestMessage::new(
+                            *client_id,
+                            RequestData::Join(JoinRequestData {
+                                name: String::from(*name),
+                            }),
+                        ))
+                        .unwrap();
+                }
+                let room_id = match next_for(&mut inboxes, bob).await {
+                    ResponseData::Joined(joined) => joined.room.id,
+                    output => panic!("Expected Output::Joined got {:?}", output),
+                };
+                let post = |client_id: Uuid, text: &str, reply_to: Option<Uuid>| {
+                    sender
+                        .send(RequestMessage::new(
+                            client_id,
+                            RequestData::PostMessage(PostMessageRequestData {
+                                room_id,
+                                text: String::from(text),
+                                reply_to,
+                            }),
+                        ))
+                        .unwrap();
+                };
+                let posted = |output: ResponseData| match output {
+                    ResponseData::Posted(posted) => posted.message,
+                    output => panic!("Expected Output::Posted got {:?}", output),
+                };
+
+                post(bob, "Lunch?", None);
+                let root_id = posted(next_for(&mut inboxes, bob).await).id;
+                post(bob, "Noon works", Some(root_id));
+                let reply_id = posted(next_for(&mut inboxes, bob).await).id;
+                // Replying to a reply lands in the same thread
+                post(bob, "Or one", Some(reply_id));
+                assert_eq!(
+                    posted(next_for(&mut inboxes, bob).await).parent_id,
+                    Some(root_id)
+                );
+                post(bob, "Who?", Some(Uuid::new_v4()));
+                let output = next_for(&mut inboxes, bob).await;
+                assert_eq!(output, ResponseData::Error(ErrorType::MessageNotFound));
+
+                sender
+                    .send(RequestMessage::new(
+                        alice,
+                        RequestData::FetchThread(FetchThreadRequestData { root_id }),
+                    ))
+                    .unwrap();
+                loop {
+                    match next_for(&mut inboxes, alice).await {
+                        ResponseData::Thread(thread) => {
+                            assert_eq!(thread.root.reply_count, 2);
+                            let texts: Vec<&str> = thread
+                                .replies
+                                .iter()
+                                .map(|reply| reply.text.as_str())
+                                .collect();
+                            assert_eq!(texts, vec!["Noon works", "Or one"]);
+                            break;
+                        }
+                        ResponseData::Joined(_)
+                        | ResponseData::UserJoined(_)
+                        | ResponseData::UserPosted(_) => continue,
+                        output => panic!("Expected Output::Thread got {:?}", output),
+                    }
+                }
+
+                sender
+                    .send(RequestMessage::new(
+                        alice,
+                        RequestData::FetchThread(FetchThreadRequestData { root_id: reply_id }),
+                    ))
+                    .unwrap();
+                let output = next_for(&mut inboxes, alice).await;
+                assert_eq!(output, ResponseData::Error(ErrorType::MessageNotFound));
+            };
+            tokio::select! {
+              _ = worker.run(receiver) => {},
+              _ = case => {},
+            }
+        });
+    }
+
+    #[test]
+    fn mentions_reach_users_outside_the_room() {
+        let worker = Worker::new(None);
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async move {
+            let case = async {
+                let alice = Uuid::new_v4();
+                let bob = Uuid::new_v4();
+                let mut inboxes = connect(&worker, &[alice, bob]).await;
+                for (client_id, name) in [(alice, "alice"), (bob, "bobby")].iter() {
+                    sender
+                        .send(RequestMessage::new(
+                            *client_id,
+                            RequestData::Join(JoinRequestData {
+                                name: String::from(*name),
+                            }),
+                        ))
+                        .unwrap();
+                }
+                sender
+                    .send(RequestMessage::new(
+                        alice,
+                        RequestData::CreateRoom(CreateRoomRequestData {
+                            name: String::from("ops"),
+                        }),
+                    ))
+                    .unwrap();
+                let room_id = loop {
+                    match next_for(&mut inboxes, alice).await {
+                        ResponseData::RoomCreated(room) => break room.id,
+                        ResponseData::Joined(_) | ResponseData::UserJoined(_) => continue,
+                        output => panic!("Expected Output::RoomCreated got {:?}", output),
+                    }
+                };
+                for request_data in [
+                    RequestData::JoinRoom(RoomRequestData { room_id }),
+                    RequestData::PostMessage(PostMessageRequestData {
+                        room_id,
+                        text: String::from("@BOBBY deploy is green, cc @bobby @nobody"),
+                        reply_to: None,
+                    }),
+                ] {
+                    sender
+                        .send(RequestMessage::new(alice, request_data))
+                        .unwrap();
+                }
 
-    use std::time::Duration;
+                let mentioned = loop {
+                    match next_for(&mut inboxes, bob).await {
+                        ResponseData::Mentioned(mentioned) => break mentioned.message,
+                        ResponseData::Joined(_)
+                        | ResponseData::UserJoined(_)
+                        | ResponseData::RoomCreated(_) => continue,
+                        output => panic!("Expected Output::Mentioned got {:?}", output),
+                    }
+                };
+                assert_eq!(mentioned.room_id, room_id);
+                let bob_id = worker.users.read().await[&bob].id;
+                assert_eq!(mentioned.mentions, vec![bob_id]);
 
-    use tokio::{runtime::Runtime, sync::mpsc};
-    use uuid::Uuid;
+                sender
+                    .send(RequestMessage::new(bob, RequestData::FetchMentions))
+                    .unwrap();
+                match next_for(&mut inboxes, bob).await {
+                    ResponseData::Mentions(mentions) => {
+                        assert_eq!(mentions.messages, vec![mentioned])
+                    }
+                    output => panic!("Expected Output::Mentions got {:?}", output),
+                }
+            };
+            tokio::select! {
+              _ = worker.run(receiver) => {},
+              _ = case => {},
+            }
+        });
+
+        let users = [
+            User::new(Uuid::new_v4(), "Bob"),
+            User::new(Uuid::new_v4(), "Bob Smith"),
+        ];
+        let mentioned = |text: &str| Worker::mentioned_user_ids(text, users.iter(), Uuid::nil());
+        assert_eq!(mentioned("@bob smith, see above"), vec![users[1].id]);
+        assert_eq!(mentioned("@Bob: and @bob"), vec![users[0].id]);
+        assert!(mentioned("mail bob@bob.dev or @bobby").is_empty());
+    }
+
+    #[test]
+    fn search_ranks_matches_by_recency() {
+        let worker = Worker::new(None);
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async move {
+            let case = async {
+                let alice = Uuid::new_v4();
+                let mut inboxes = connect(&worker, &[alice]).await;
+                sender
+                    .send(RequestMessage::new(
+                        alice,
+                        RequestData::Join(JoinRequestData {
+                            name: String::from("alice"),
+                        }),
+                    ))
+                    .unwrap();
+                let (alice_id, room_id) = match next_for(&mut inboxes, alice).await {
+                    ResponseData::Joined(joined) => (joined.user.id, joined.room.id),
+                    output => panic!("Expected Output::Joined got {:?}", output),
+                };
+                let mut message_ids = Vec::new();
+                for text in [
+                    "Deploy the green build",
+                    "deploy failed",
+                    "Green build is deployed",
+                    "Deploy: GREEN build, take two",
+                ]
+                .iter()
+                {
+                    sender
+                        .send(RequestMessage::new(
+                            alice,
+                            RequestData::PostMessage(PostMessageRequestData {
+                                room_id,
+                                text: String::from(*text),
+                                reply_to: None,
+                            }),
+                        ))
+                        .unwrap();
+                    match next_for(&mut inboxes, alice).await {
+                        ResponseData::Posted(posted) => message_ids.push(posted.message.id),
+                        output => panic!("Expected Output::Posted got {:?}", output),
+                    }
+                }
+                // The index follows edits and deletes
+                sender
+                    .send(RequestMessage::new(
+                        alice,
+                        RequestData::EditMessage(EditMessageRequestData {
+                            id: message_ids[1],
+                            text: String::from("deploy of the green build failed"),
+                        }),
+                    ))
+                    .unwrap();
+                sender
+                    .send(RequestMessage::new(
+                        alice,
+                        RequestData::DeleteMessage(DeleteMessageRequestData { id: message_ids[3] }),
+                    ))
+                    .unwrap();
 
-    use crate::protocol::{request::{JoinRequestData, PostMessageRequestData, RequestData, RequestMessage}, response::ResponseData};
+                let search = |query: &str, from_user: Option<Uuid>, limit: usize| {
+                    sender
+                        .send(RequestMessage::new(
+                            alice,
+                            RequestData::Search(SearchRequestData {
+                                query: String::from(query),
+                                from_user,
+                                before: None,
+                                after: None,
+                                limit,
+                            }),
+                        ))
+                        .unwrap();
+                };
+                search(r#"DEPLOY "green build""#, None, 10);
+                search("deploy", Some(alice_id), 1);
+                search("deploy", Some(Uuid::new_v4()), 10);
+                search("...", None, 10);
 
-    use super::Worker;
+                let mut results = Vec::new();
+                while results.len() < 3 {
+                    match next_for(&mut inboxes, alice).await {
+                        ResponseData::SearchResults(found) => results.push(
+                            found
+                                .messages
+                                .iter()
+                                .map(|message| message.id)
+                                .collect::<Vec<_>>(),
+                        ),
+                        ResponseData::MessageEdited(_) | ResponseData::MessageDeleted(_) => {}
+                        output => panic!("Expected Output::SearchResults got {:?}", output),
+                    }
+                }
+                assert_eq!(results[0], vec![message_ids[1], message_ids[0]]);
+                assert_eq!(results[1], vec![message_ids[1]]);
+                assert!(results[2].is_empty());
+                let output = next_for(&mut inboxes, alice).await;
+                assert_eq!(output, ResponseData::Error(ErrorType::InvalidQuery));
+            };
+            tokio::select! {
+              _ = worker.run(receiver) => {},
+              _ = case => {},
+            }
+        });
+    }
 
     #[test]
-    fn join_and_post() {
-        let worker = Worker::new(Some(Duration::from_secs(1)));
+    fn moderators_mute_and_ban() {
+        let steward_id = Uuid::new_v4();
+        let config = ServerConfig {
+            moderators: vec![String::from("warden"), String::from("keeper")],
+            moderator_accounts: vec![steward_id],
+            ..ServerConfig::default()
+        };
+        let worker = Worker::with_config(&config, Box::new(MemoryFeedStore::default())).unwrap();
         let (sender, receiver) = mpsc::unbounded_channel();
-        let mut subscription = worker.subscribe();
 
-        let mut rt = Runtime::new().unwrap();
+        let rt = Runtime::new().unwrap();
         rt.block_on(async move {
             let case = async {
-                let client_id = Uuid::new_v4();
+                let warden = Uuid::new_v4();
+                let troll = Uuid::new_v4();
+                let mut inboxes = connect(&worker, &[troll]).await;
+                let mut client = Client::with_identity(Some(Identity::new("w-1", "warden")));
+                client.id = warden;
+                inboxes.insert(warden, worker.on_connect(&client).await);
 
-                // Join
                 sender
                     .send(RequestMessage::new(
-                        client_id,
+                        warden,
                         RequestData::Join(JoinRequestData {
-                            name: String::from("daolavi"),
+                            name: String::from("warden"),
                         }),
                     ))
                     .unwrap();
-                let output = subscription.recv().await.unwrap().response_data;
-                println!("{:?}", output);
-                let user;
-                if let ResponseData::Joined(joined) = output {
-                    assert_eq!(joined.user.name.as_str(), "daolavi");
-                    user = joined.user;
-                } else {
-                    panic!("Expected Output::Joined got {:?}", output);
+                let warden_id = match next_for(&mut inboxes, warden).await {
+                    ResponseData::Joined(joined) => {
+                        assert_eq!(joined.user.role, Role::Moderator);
+                        joined.user.id
+                    }
+                    output => panic!("Expected Output::Joined got {:?}", output),
+                };
+
+                sender
+                    .send(RequestMessage::new(
+                        troll,
+                        RequestData::Join(JoinRequestData {
+                            name: String::from("troll"),
+                        }),
+                    ))
+                    .unwrap();
+                let (troll_id, room_id) = match next_for(&mut inboxes, troll).await {
+                    ResponseData::Joined(joined) => {
+                        assert_eq!(joined.user.role, Role::Member);
+                        (joined.user.id, joined.room.id)
+                    }
+                    output => panic!("Expected Output::Joined got {:?}", output),
+                };
+                assert!(matches!(
+                    next_for(&mut inboxes, warden).await,
+                    ResponseData::UserJoined(_)
+                ));
+
+                let mute = |user_id: Option<Uuid>, name: Option<&str>| {
+                    RequestData::Mute(MuteRequestData {
+                        user_id,
+                        name: name.map(String::from),
+                        duration_secs: 60,
+                    })
+                };
+                sender
+                    .send(RequestMessage::new(troll, mute(Some(troll_id), None)))
+                    .unwrap();
+                let output = next_for(&mut inboxes, troll).await;
+                assert_eq!(output, ResponseData::Error(ErrorType::NotModerator));
+
+                sender
+                    .send(RequestMessage::new(warden, mute(Some(troll_id), None)))
+                    .unwrap();
+                for client_id in &[warden, troll] {
+                    match next_for(&mut inboxes, *client_id).await {
+                        ResponseData::UserMuted(muted) => {
+                            assert_eq!(muted.name, "troll");
+                            assert_eq!(muted.user_id, Some(troll_id));
+                            assert!(muted.until_utc.is_some());
+                        }
+                        output => panic!("Expected Output::UserMuted got {:?}", output),
+                    }
                 }
 
-                // Post message
                 sender
                     .send(RequestMessage::new(
-                        client_id,
+                        troll,
                         RequestData::PostMessage(PostMessageRequestData {
-                            text: String::from("Hello"),
+                            room_id,
+                            text: String::from("Spam"),
+                            reply_to: None,
                         }),
                     ))
                     .unwrap();
-                let output = subscription.recv().await.unwrap().response_data;
-                if let ResponseData::Posted(posted) = output {
-                    assert_eq!(posted.message.text, "Hello");
-                    assert_eq!(posted.message.user.id, user.id);
-                    assert_eq!(posted.message.user.name, user.name);
-                } else {
-                    panic!("Expected Output::Posted got {:?}", output);
+                let output = next_for(&mut inboxes, troll).await;
+                assert_eq!(output, ResponseData::Error(ErrorType::Muted));
+
+                // Nor can they publish by other means
+                for request_data in [
+                    RequestData::DirectMessage(DirectMessageRequestData {
+                        to: warden_id,
+                        text: String::from("Spam"),
+                    }),
+                    RequestData::EditMessage(EditMessageRequestData {
+                        id: Uuid::new_v4(),
+                        text: String::from("Spam"),
+                    }),
+                ] {
+                    sender
+                        .send(RequestMessage::new(troll, request_data))
+                        .unwrap();
+                    let output = next_for(&mut inboxes, troll).await;
+                    assert_eq!(output, ResponseData::Error(ErrorType::Muted));
                 }
 
-                return;
+                sender
+                    .send(RequestMessage::new(
+                        warden,
+                        RequestData::Ban(BanRequestData {
+                            user_id: Some(troll_id),
+                            name: Some(String::from("troll")),
+                            ip: None,
+                        }),
+                    ))
+                    .unwrap();
+                let output = next_for(&mut inboxes, warden).await;
+                assert!(matches!(
+                    output,
+                    ResponseData::Error(ErrorType::InvalidRequest { line: 0, .. })
+                ));
+
+                sender
+                    .send(RequestMessage::new(
+                        warden,
+                        RequestData::Ban(BanRequestData {
+                            user_id: Some(troll_id),
+                            name: None,
+                            ip: None,
+                        }),
+                    ))
+                    .unwrap();
+                match next_for(&mut inboxes, warden).await {
+                    ResponseData::UserLeft(left) => assert_eq!(left.user_id, troll_id),
+                    output => panic!("Expected Output::UserLeft got {:?}", output),
+                }
+                match next_for(&mut inboxes, warden).await {
+                    ResponseData::UserBanned(banned) => {
+                        assert_eq!(banned.ban, Ban::Name(String::from("troll")))
+                    }
+                    output => panic!("Expected Output::UserBanned got {:?}", output),
+                }
+                assert!(!worker.users.read().await.contains_key(&troll));
+
+                let returning = Uuid::new_v4();
+                let mut inboxes = connect(&worker, &[returning]).await;
+                sender
+                    .send(RequestMessage::new(
+                        returning,
+                        RequestData::Join(JoinRequestData {
+                            name: String::from("troll"),
+                        }),
+                    ))
+                    .unwrap();
+                let output = next_for(&mut inboxes, returning).await;
+                assert_eq!(output, ResponseData::Error(ErrorType::Banned));
+
+                // Listed names are only trusted from tokens, and accounts by id
+                let password_hash = hash_password("correct horse").unwrap();
+                worker
+                    .feed
+                    .write()
+                    .await
+                    .add_account(Account::new(
+                        steward_id,
+                        "steward",
+                        &password_hash,
+                        Utc::now(),
+                    ))
+                    .unwrap();
+                let impostor = Uuid::new_v4();
+                let steward = Uuid::new_v4();
+                let mut inboxes = connect(&worker, &[impostor, steward]).await;
+                let credentials = |name: &str| CredentialsRequestData {
+                    name: String::from(name),
+                    password: String::from("correct horse"),
+                };
+                sender
+                    .send(RequestMessage::new(
+                        impostor,
+                        RequestData::Register(credentials("keeper")),
+                    ))
+                    .unwrap();
+                match next_for(&mut inboxes, impostor).await {
+                    ResponseData::Joined(joined) => assert_eq!(joined.user.role, Role::Member),
+                    output => panic!("Expected Output::Joined got {:?}", output),
+                }
+                sender
+                    .send(RequestMessage::new(
+                        steward,
+                        RequestData::Login(credentials("steward")),
+                    ))
+                    .unwrap();
+                match next_for(&mut inboxes, steward).await {
+                    ResponseData::Joined(joined) => {
+                        assert_eq!(joined.user.role, Role::Moderator)
+                    }
+                    output => panic!("Expected Output::Joined got {:?}", output),
+                }
+
+                // Mutes hold for the name, whoever joins under it and whenever
+                sender
+                    .send(RequestMessage::new(steward, mute(None, Some("ghost"))))
+                    .unwrap();
+                match next_for(&mut inboxes, steward).await {
+                    ResponseData::UserMuted(muted) => assert_eq!(muted.user_id, None),
+                    output => panic!("Expected Output::UserMuted got {:?}", output),
+                }
+                let ghost = Uuid::new_v4();
+                let mut inboxes = connect(&worker, &[ghost]).await;
+                sender
+                    .send(RequestMessage::new(
+                        ghost,
+                        RequestData::Join(JoinRequestData {
+                            name: String::from("ghost"),
+                        }),
+                    ))
+                    .unwrap();
+                let room_id = match next_for(&mut inboxes, ghost).await {
+                    ResponseData::Joined(joined) => joined.room.id,
+                    output => panic!("Expected Output::Joined got {:?}", output),
+                };
+                sender
+                    .send(RequestMessage::new(
+                        ghost,
+                        RequestData::PostMessage(PostMessageRequestData {
+                            room_id,
+                            text: String::from("Boo"),
+                            reply_to: None,
+                        }),
+                    ))
+                    .unwrap();
+                let output = next_for(&mut inboxes, ghost).await;
+                assert_eq!(output, ResponseData::Error(ErrorType::Muted));
             };
+
             tokio::select! {
               _ = worker.run(receiver) => {},
-              _ = case => {},
+              _ = case => {}
+            }
+        });
+    }
+
+    #[test]
+    fn banned_users_cannot_resume() {
+        let config = ServerConfig {
+            moderators: vec![String::from("warden")],
+            ..ServerConfig::default()
+        };
+        let worker = Worker::with_config(&config, Box::new(MemoryFeedStore::default())).unwrap();
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async move {
+            let case = async {
+                let mut inboxes = HashMap::new();
+                let connect_from = |address: &str| {
+                    let mut client = Client::new();
+                    client.address = Some(address.parse().unwrap());
+                    client
+                };
+                let warden = Uuid::new_v4();
+                let mut client = Client::with_identity(Some(Identity::new("w-1", "warden")));
+                client.id = warden;
+                inboxes.insert(warden, worker.on_connect(&client).await);
+                let join = |client_id: Uuid, name: &str| {
+                    RequestMessage::new(
+                        client_id,
+                        RequestData::Join(JoinRequestData {
+                            name: String::from(name),
+                        }),
+                    )
+                };
+                sender.send(join(warden, "warden")).unwrap();
+                next_for(&mut inboxes, warden).await;
+
+                let mut tokens = HashMap::new();
+                for (name, address) in [
+                    ("troll", "203.0.113.7"),
+                    ("rogue", "198.51.100.4"),
+                    ("bystander", "192.0.2.1"),
+                ] {
+                    let client = connect_from(address);
+                    inboxes.insert(client.id, worker.on_connect(&client).await);
+                    sender.send(join(client.id, name)).unwrap();
+                    match next_for(&mut inboxes, client.id).await {
+                        ResponseData::Joined(joined) => tokens.insert(name, joined.resume_token),
+                        output => panic!("Expected Output::Joined got {:?}", output),
+                    };
+                    worker.on_disconnect(client.id).await;
+                    assert!(matches!(
+                        next_for(&mut inboxes, warden).await,
+                        ResponseData::UserJoined(_)
+                    ));
+                }
+
+                for (name, ip) in [
+                    (Some("troll"), None),
+                    (None, Some("198.51.100.4".parse().unwrap())),
+                ] {
+                    sender
+                        .send(RequestMessage::new(
+                            warden,
+                            RequestData::Ban(BanRequestData {
+                                user_id: None,
+                                name: name.map(String::from),
+                                ip,
+                            }),
+                        ))
+                        .unwrap();
+                    assert!(matches!(
+                        next_for(&mut inboxes, warden).await,
+                        ResponseData::UserBanned(_)
+                    ));
+                }
+
+                let resume = |name: &str, address: &str| {
+                    let client = connect_from(address);
+                    let request_message = RequestMessage::new(
+                        client.id,
+                        RequestData::Resume(ResumeRequestData {
+                            token: tokens[name].clone(),
+                            last_seen_message_id: None,
+                        }),
+                    );
+                    (client, request_message)
+                };
+                for (name, address, expected) in [
+                    ("troll", "192.0.2.50", ErrorType::Banned),
+                    ("rogue", "192.0.2.50", ErrorType::InvalidResumeToken),
+                    ("bystander", "198.51.100.4", ErrorType::Banned),
+                ] {
+                    let (client, request_message) = resume(name, address);
+                    inboxes.insert(client.id, worker.on_connect(&client).await);
+                    sender.send(request_message).unwrap();
+                    let output = next_for(&mut inboxes, client.id).await;
+                    assert_eq!(output, ResponseData::Error(expected));
+                }
+
+                // Being refused for the address does not use the token up
+                let (client, request_message) = resume("bystander", "192.0.2.50");
+                inboxes.insert(client.id, worker.on_connect(&client).await);
+                sender.send(request_message).unwrap();
+                match next_for(&mut inboxes, client.id).await {
+                    ResponseData::Resumed(resumed) => assert_eq!(resumed.user.name, "bystander"),
+                    output => panic!("Expected Output::Resumed got {:?}", output),
+                }
+            };
+
+            tokio::select! {
+              _ = worker.run(receiver) => {},
+              _ = case => {}
+            }
+        });
+    }
+
+    #[test]
+    fn flooding_clients_are_rate_limited_then_disconnected() {
+        let config = ServerConfig {
+            rate_limits: vec![(String::from("postMessage"), "1/60".parse().unwrap())]
+                .into_iter()
+                .collect(),
+            rate_limit_strikes: 2,
+            ..ServerConfig::default()
+        };
+        let worker = Worker::with_config(&config, Box::new(MemoryFeedStore::default())).unwrap();
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async move {
+            let case = async {
+                let flooder = Uuid::new_v4();
+                let mut inboxes = connect(&worker, &[flooder]).await;
+                sender
+                    .send(RequestMessage::new(
+                        flooder,
+                        RequestData::Join(JoinRequestData {
+                            name: String::from("flooder"),
+                        }),
+                    ))
+                    .unwrap();
+                let room_id = match next_for(&mut inboxes, flooder).await {
+                    ResponseData::Joined(joined) => joined.room.id,
+                    output => panic!("Expected Output::Joined got {:?}", output),
+                };
+                let post = || {
+                    RequestMessage::new(
+                        flooder,
+                        RequestData::PostMessage(PostMessageRequestData {
+                            room_id,
+                            text: String::from("Buy now"),
+                            reply_to: None,
+                        }),
+                    )
+                };
+
+                sender.send(post()).unwrap();
+                assert!(matches!(
+                    next_for(&mut inboxes, flooder).await,
+                    ResponseData::Posted(_)
+                ));
+
+                sender.send(post()).unwrap();
+                match next_for(&mut inboxes, flooder).await {
+                    ResponseData::Error(ErrorType::RateLimited { retry_after_ms }) => {
+                        assert!(retry_after_ms > 59_000 && retry_after_ms <= 60_000)
+                    }
+                    output => panic!("Expected Output::Error got {:?}", output),
+                }
+
+                sender.send(post()).unwrap();
+                let closing: Vec<Outbound> = inboxes
+                    .remove(&flooder)
+                    .unwrap()
+                    .into_stream()
+                    .collect()
+                    .await;
+                assert!(matches!(
+                    closing.as_slice(),
+                    [
+                        Outbound::Response(ResponseMessage {
+                            response_data: ResponseData::Error(ErrorType::RateLimited { .. }),
+                            ..
+                        }),
+                        Outbound::Close(RATE_LIMITED_CLOSE_CODE, _)
+                    ]
+                ));
+                assert!(!worker.users.read().await.contains_key(&flooder));
+            };
+
+            tokio::select! {
+              _ = worker.run(receiver) => {},
+              _ = case => {}
+            }
+        });
+    }
+
+    #[test]
+    fn neighbours_of_flooders_are_limited_but_not_disconnected() {
+        let limits = |limit: &str| {
+            vec![(String::from("postMessage"), limit.parse().unwrap())]
+                .into_iter()
+                .collect()
+        };
+        let config = ServerConfig {
+            rate_limits: limits("5/60"),
+            address_rate_limits: limits("1/60"),
+            rate_limit_strikes: 1,
+            ..ServerConfig::default()
+        };
+        let worker = Worker::with_config(&config, Box::new(MemoryFeedStore::default())).unwrap();
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async move {
+            let case = async {
+                let address = Some("203.0.113.7".parse().unwrap());
+                let flooder = Uuid::new_v4();
+                let neighbour = Uuid::new_v4();
+                let mut inboxes = HashMap::new();
+                for (client_id, name) in [(flooder, "flooder"), (neighbour, "neighbour")].iter() {
+                    let mut client = Client::new().with_address(address);
+                    client.id = *client_id;
+                    inboxes.insert(*client_id, worker.on_connect(&client).await);
+                    sender
+                        .send(RequestMessage::new(
+                            *client_id,
+                            RequestData::Join(JoinRequestData {
+                                name: String::from(*name),
+                            }),
+                        ))
+                        .unwrap();
+                }
+                let room_id = match next_for(&mut inboxes, neighbour).await {
+                    ResponseData::Joined(joined) => joined.room.id,
+                    output => panic!("Expected Output::Joined got {:?}", output),
+                };
+                let post = |client_id: Uuid| {
+                    RequestMessage::new(
+                        client_id,
+                        RequestData::PostMessage(PostMessageRequestData {
+                            room_id,
+                            text: String::from("Buy now"),
+                            reply_to: None,
+                        }),
+                    )
+                };
+
+                sender.send(post(flooder)).unwrap();
+                assert!(matches!(
+                    next_for(&mut inboxes, neighbour).await,
+                    ResponseData::UserPosted(_)
+                ));
+                for _ in 0..3 {
+                    sender.send(post(neighbour)).unwrap();
+                    assert!(matches!(
+                        next_for(&mut inboxes, neighbour).await,
+                        ResponseData::Error(ErrorType::RateLimited { .. })
+                    ));
+                }
+                sender
+                    .send(RequestMessage::new(neighbour, RequestData::ListRooms))
+                    .unwrap();
+                assert!(matches!(
+                    next_for(&mut inboxes, neighbour).await,
+                    ResponseData::Rooms(_)
+                ));
+            };
+
+            tokio::select! {
+              _ = worker.run(receiver) => {},
+              _ = case => {}
+            }
+        });
+    }
+
+    #[test]
+    fn request_ids_are_echoed_on_replies() {
+        let worker = Worker::new(None);
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async move {
+            let case = async {
+                let alice = Uuid::new_v4();
+                let bob = Uuid::new_v4();
+                let mut inboxes = connect(&worker, &[alice, bob]).await;
+                let join = |client_id: Uuid, request_id: &str| {
+                    let request: RequestEnvelope = serde_json::from_str(&format!(
+                        r#"{{"requestId": "{}", "type": "join", "payload": {{"name": "alice"}}}}"#,
+                        request_id
+                    ))
+                    .unwrap();
+                    RequestMessage::new(client_id, request.request_data)
+                        .with_request_id(request.request_id)
+                };
+
+                sender.send(join(alice, "a-1")).unwrap();
+                let joined = inboxes
+                    .get_mut(&alice)
+                    .unwrap()
+                    .recv_message()
+                    .await
+                    .unwrap();
+                assert_eq!(joined.request_id.as_deref(), Some("a-1"));
+                let room_id = match joined.response_data {
+                    ResponseData::Joined(joined) => joined.room.id,
+                    output => panic!("Expected Output::Joined got {:?}", output),
+                };
+
+                sender.send(join(bob, "b-1")).unwrap();
+                let error = inboxes.get_mut(&bob).unwrap().recv_message().await.unwrap();
+                assert_eq!(
+                    serde_json::to_value(&error).unwrap(),
+                    serde_json::json!({
+                        "requestId": "b-1",
+                        "type": "Error",
+                        "payload": "NameExisted",
+                    })
+                );
+
+                sender
+                    .send(
+                        RequestMessage::new(
+                            alice,
+                            RequestData::PostMessage(PostMessageRequestData {
+                                room_id,
+                                text: String::from("Hello"),
+                                reply_to: None,
+                            }),
+                        )
+                        .with_request_id(Some(String::from("a-2"))),
+                    )
+                    .unwrap();
+                let posted = inboxes
+                    .get_mut(&alice)
+                    .unwrap()
+                    .recv_message()
+                    .await
+                    .unwrap();
+                assert!(matches!(posted.response_data, ResponseData::Posted(_)));
+                assert_eq!(posted.request_id.as_deref(), Some("a-2"));
+
+                // Only the reply to the requester carries the id
+                sender
+                    .send(RequestMessage::new(
+                        bob,
+                        RequestData::Join(JoinRequestData {
+                            name: String::from("bobby"),
+                        }),
+                    ))
+                    .unwrap();
+                let joined = inboxes.get_mut(&bob).unwrap().recv_message().await.unwrap();
+                assert_eq!(joined.request_id, None);
+                let user_joined = inboxes
+                    .get_mut(&alice)
+                    .unwrap()
+                    .recv_message()
+                    .await
+                    .unwrap();
+                assert!(matches!(
+                    user_joined.response_data,
+                    ResponseData::UserJoined(_)
+                ));
+                assert_eq!(user_joined.request_id, None);
+            };
+
+            tokio::select! {
+              _ = worker.run(receiver) => {},
+              _ = case => {}
             }
         });
     }